@@ -1,64 +1,225 @@
 use std::{
     ops::Div,
-    sync::mpsc::{self, Receiver, Sender, SyncSender},
+    ptr,
+    sync::{
+        Arc,
+        Mutex,
+        atomic::{ AtomicUsize, Ordering },
+        mpsc::{ self, Receiver, Sender, SyncSender, TrySendError },
+    },
 };
 
-use ffmpeg_next::frame::Audio;
+use ffmpeg_next::{ DictionaryRef, Rational, format::Sample, frame::Audio, rescale };
 use sdl3::{
     Error, Sdl,
-    audio::{AudioCallback, AudioFormatNum, AudioSpec, AudioStreamWithCallback},
+    audio::{ AudioCallback, AudioFormat, AudioFormatNum, AudioSpec, AudioStreamWithCallback },
 };
+use sdl3_sys::audio::{ SDL_AUDIO_DEVICE_DEFAULT_PLAYBACK, SDL_AudioSpec, SDL_GetAudioDeviceFormat };
 
+/// The spec decoded audio is converted to before it reaches SDL: float samples at the default
+/// device's rate, stereo unless the device has a single channel. Falls back to 48 kHz stereo when
+/// the device cannot be queried.
+pub fn negotiate_output_spec(sdl: &Sdl) -> AudioSpec {
+    let mut freq = 48000;
+    let mut channels = 2;
+    // The subsystem has to be up for the query
+    if let Ok(_audio) = sdl.audio() {
+        let mut device = SDL_AudioSpec::default();
+        let queried = unsafe {
+            SDL_GetAudioDeviceFormat(SDL_AUDIO_DEVICE_DEFAULT_PLAYBACK, &mut device, ptr::null_mut())
+        };
+        if queried && device.freq > 0 {
+            freq = device.freq;
+            channels = device.channels.clamp(1, 2);
+        }
+    }
+    AudioSpec {
+        freq: Some(freq),
+        channels: Some(channels),
+        format: Some(AudioFormat::F32LE),
+    }
+}
+
+/// Frames sent to the returned `MPlayerAudio` must carry their pts in AV_TIME_BASE units, on a
+/// timeline that keeps going across files so the stream can be reused for gapless playback.
 pub fn init_audio_subsystem(sdl: &Sdl, spec: AudioSpec) -> Result<MPlayerAudio, Error> {
     let audio = sdl.audio()?;
     let (tx, rx) = mpsc::sync_channel(100);
-    let ctx = MPlayerAudioCallbackCtx::new(rx);
-    let mut spec = spec;
-    spec.channels = Some(2);
+
+    let bytes_per_sec = (spec.freq.unwrap_or(0) *
+        spec.channels.unwrap_or(0) *
+        bytes_per_sample(spec.format.unwrap_or(AudioFormat::UNKNOWN))) as f64;
+    let queued_until = Arc::new(Mutex::new(None));
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let ctx = MPlayerAudioCallbackCtx::new(
+        rx,
+        rescale::TIME_BASE,
+        bytes_per_sec,
+        Arc::clone(&queued_until),
+        Arc::clone(&in_flight)
+    );
 
     let device = match spec.format {
-        Some(sdl3::audio::AudioFormat::S16LE) => {
+        Some(AudioFormat::S16LE) => {
             audio.open_playback_stream::<MPlayerAudioCallbackCtx, i16>(&spec, ctx)?
         }
-        Some(sdl3::audio::AudioFormat::S32LE) => {
+        Some(AudioFormat::S32LE) => {
             audio.open_playback_stream::<MPlayerAudioCallbackCtx, i32>(&spec, ctx)?
         }
-        Some(sdl3::audio::AudioFormat::F32LE) => {
-            let mut spec = spec;
-            if let Some(freq) = spec.freq {
-                let _ = spec.freq.insert(freq / spec.channels.unwrap());
-            }
+        Some(AudioFormat::F32LE) => {
             audio.open_playback_stream::<MPlayerAudioCallbackCtx, f32>(&spec, ctx)?
         }
-        Some(sdl3::audio::AudioFormat::U8) => {
+        Some(AudioFormat::U8) => {
             audio.open_playback_stream::<MPlayerAudioCallbackCtx, u8>(&spec, ctx)?
         }
-        Some(sdl3::audio::AudioFormat::S8) => {
+        Some(AudioFormat::S8) => {
             audio.open_playback_stream::<MPlayerAudioCallbackCtx, i8>(&spec, ctx)?
         }
         _ => {
-            panic!("Unsupported audio format.");
+            // Frames go through the resampler first, this only happens without one
+            let _ = sdl3::set_error("unsupported audio format, the frames need resampling first");
+            return Err(sdl3::get_error());
         }
     };
     let _ = device.resume();
 
-    let audio_sys = MPlayerAudio { tx, device: device };
+    let audio_sys = MPlayerAudio {
+        tx,
+        device: device,
+        queued_until,
+        bytes_per_sec,
+        speed: 1.0,
+        in_flight,
+        spec,
+        gain: 1.0,
+    };
 
     Ok(audio_sys)
 }
 
+fn bytes_per_sample(format: AudioFormat) -> i32 {
+    match format {
+        AudioFormat::U8 | AudioFormat::S8 => 1,
+        AudioFormat::S16LE | AudioFormat::S16BE => 2,
+        AudioFormat::S32LE | AudioFormat::S32BE | AudioFormat::F32LE | AudioFormat::F32BE => 4,
+        AudioFormat::UNKNOWN => 0,
+    }
+}
+
 pub struct MPlayerAudio {
     pub tx: SyncSender<Audio>,
     pub device: AudioStreamWithCallback<MPlayerAudioCallbackCtx>,
+    // Media time (in seconds) at the end of the last frame handed to SDL
+    queued_until: Arc<Mutex<Option<f64>>>,
+    bytes_per_sec: f64,
+    // Tempo of the frames being fed, one second of output covers `speed` seconds of media
+    speed: f64,
+    // Frames sent through `tx` that the callback has not picked up yet
+    in_flight: Arc<AtomicUsize>,
+    // Spec the stream was opened with
+    spec: AudioSpec,
+    // Linear factor applied to the samples, 0 when muted
+    gain: f32,
+}
+
+impl MPlayerAudio {
+    /// Whether frames decoded with `spec` can go through this stream as they are.
+    pub fn accepts(&self, spec: &AudioSpec) -> bool {
+        self.spec.freq == spec.freq &&
+            self.spec.channels == spec.channels &&
+            self.spec.format == spec.format
+    }
+
+    pub fn try_send(&self, frame: Audio) -> Result<(), TrySendError<Audio>> {
+        // Counted before sending, the callback may pick the frame up right away
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.tx.try_send(frame).inspect_err(|_| {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        })
+    }
+
+    /// Everything that was sent has been played out.
+    pub fn is_idle(&self) -> bool {
+        self.in_flight.load(Ordering::SeqCst) == 0 && self.device.queued_bytes().unwrap_or(0) == 0
+    }
+
+    /// Applies to the frames handed to SDL from now on, what is already queued on the stream plays
+    /// out at the previous level.
+    pub fn set_gain(&mut self, gain: f32) {
+        if self.gain == gain {
+            return;
+        }
+        if let Some(mut ctx) = self.device.lock() {
+            ctx.gain = gain;
+        }
+        self.gain = gain;
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        if let Some(mut ctx) = self.device.lock() {
+            ctx.speed = speed;
+        }
+        self.speed = speed;
+    }
+
+    /// Drops every frame that has not been played yet, both the ones waiting in the channel and
+    /// the samples already queued on the SDL stream.
+    pub fn clear(&mut self) {
+        if let Some(mut ctx) = self.device.lock() {
+            ctx.drain();
+        }
+        let _ = self.device.clear();
+        if let Ok(mut queued_until) = self.queued_until.lock() {
+            *queued_until = None;
+        }
+    }
+
+    /// Media time (in seconds) of the sample the device is playing right now, that is the end of
+    /// the data handed to SDL minus what is still sitting in the stream. `None` until the first
+    /// frame reached the device.
+    pub fn position(&self) -> Option<f64> {
+        let queued_until = (*self.queued_until.lock().ok()?)?;
+        let queued_bytes = self.device.queued_bytes().ok()? as f64;
+        if self.bytes_per_sec == 0.0 {
+            return Some(queued_until);
+        }
+        Some(queued_until - queued_bytes.div(self.bytes_per_sec) * self.speed)
+    }
 }
 
 pub struct MPlayerAudioCallbackCtx {
     recv: Receiver<Audio>,
+    time_base: Rational,
+    bytes_per_sec: f64,
+    queued_until: Arc<Mutex<Option<f64>>>,
+    speed: f64,
+    in_flight: Arc<AtomicUsize>,
+    gain: f32,
 }
 
 impl MPlayerAudioCallbackCtx {
-    pub fn new(audio_rx: Receiver<Audio>) -> MPlayerAudioCallbackCtx {
-        MPlayerAudioCallbackCtx { recv: audio_rx }
+    pub fn new(
+        audio_rx: Receiver<Audio>,
+        time_base: Rational,
+        bytes_per_sec: f64,
+        queued_until: Arc<Mutex<Option<f64>>>,
+        in_flight: Arc<AtomicUsize>
+    ) -> MPlayerAudioCallbackCtx {
+        MPlayerAudioCallbackCtx {
+            recv: audio_rx,
+            time_base,
+            bytes_per_sec,
+            queued_until,
+            speed: 1.0,
+            in_flight,
+            gain: 1.0,
+        }
+    }
+
+    pub fn drain(&mut self) {
+        while let Ok(_) = self.recv.try_recv() {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
     }
 }
 
@@ -66,12 +227,140 @@ impl<T> AudioCallback<T> for MPlayerAudioCallbackCtx
 where
     T: AudioFormatNum,
 {
-    fn callback(&mut self, stream: &mut sdl3::audio::AudioStream, _: i32) {
-        match self.recv.try_recv() {
-            Ok(frame) => {
-                let _ = stream.put_data(frame.data(0));
+    fn callback(&mut self, stream: &mut sdl3::audio::AudioStream, requested: i32) {
+        // Keep feeding until SDL has what it asked for, one frame is often not enough
+        let needed = (requested.max(0) as usize) * size_of::<T>();
+        let mut pushed = 0;
+        while pushed < needed {
+            let Ok(frame) = self.recv.try_recv() else {
+                break;
+            };
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            // The plane is allocated with padding (and, out of the resampler, room to spare),
+            // only the samples count. Packed audio has them all in the first plane.
+            let len = frame.samples() * (frame.channels() as usize) * frame.format().bytes();
+            let data = &frame.data(0)[..len.min(frame.data(0).len())];
+            let put = if self.gain == 1.0 {
+                stream.put_data(data)
+            } else {
+                stream.put_data(&apply_gain(data, frame.format(), self.gain))
+            };
+            if let Ok(_) = put {
+                pushed += data.len();
+                if let Some(pts) = frame.pts() && let Ok(mut queued_until) = self.queued_until.lock() {
+                    let duration = if self.bytes_per_sec > 0.0 {
+                        ((data.len() as f64) / self.bytes_per_sec) * self.speed
+                    } else {
+                        0.0
+                    };
+                    *queued_until = Some((pts as f64) * f64::from(self.time_base) + duration);
+                }
             }
-            Err(_) => {}
+        }
+    }
+}
+
+/// Linear factor for a gain in dB.
+pub fn db_to_linear(db: f32) -> f32 {
+    (10.0f32).powf(db / 20.0)
+}
+
+// Scales packed samples, integer formats saturate instead of wrapping around. Float samples are
+// left for SDL to clamp.
+fn apply_gain(data: &[u8], format: Sample, gain: f32) -> Vec<u8> {
+    match format {
+        Sample::F32(_) =>
+            data
+                .chunks_exact(4)
+                .flat_map(|bytes| {
+                    (f32::from_ne_bytes(bytes.try_into().unwrap()) * gain).to_ne_bytes()
+                })
+                .collect(),
+        Sample::I16(_) =>
+            data
+                .chunks_exact(2)
+                .flat_map(|bytes| {
+                    let sample = f32::from(i16::from_ne_bytes(bytes.try_into().unwrap())) * gain;
+                    (sample.clamp(i16::MIN.into(), i16::MAX.into()) as i16).to_ne_bytes()
+                })
+                .collect(),
+        Sample::I32(_) =>
+            data
+                .chunks_exact(4)
+                .flat_map(|bytes| {
+                    let sample =
+                        f64::from(i32::from_ne_bytes(bytes.try_into().unwrap())) * f64::from(gain);
+                    (sample.clamp(i32::MIN.into(), i32::MAX.into()) as i32).to_ne_bytes()
+                })
+                .collect(),
+        Sample::U8(_) =>
+            data
+                .iter()
+                .map(|&sample| ((f32::from(sample) - 128.0) * gain + 128.0).clamp(0.0, 255.0) as u8)
+                .collect(),
+        _ => data.to_vec(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayGainMode {
+    Off,
+    Track,
+    Album,
+}
+
+/// Loudness tags of a file, gains are in dB relative to the ReplayGain reference level and peaks
+/// are linear sample values.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+    /// Reads the REPLAYGAIN_* tags ("-6.48 dB") and Opus' R128_* ones, which are Q7.8 integers
+    /// relative to -23 LUFS, 5 dB under the ReplayGain reference.
+    pub fn from_metadata(metadata: &DictionaryRef) -> ReplayGain {
+        let gain = |key: &str| {
+            metadata.get(key).and_then(|value| {
+                value.trim().trim_end_matches("dB").trim_end_matches("db").trim().parse::<f32>().ok()
+            })
+        };
+        let r128 = |key: &str| {
+            metadata
+                .get(key)
+                .and_then(|value| value.trim().parse::<i32>().ok())
+                .map(|value| (value as f32) / 256.0 + 5.0)
+        };
+        let peak = |key: &str| metadata.get(key).and_then(|value| value.trim().parse::<f32>().ok());
+        ReplayGain {
+            track_gain: gain("REPLAYGAIN_TRACK_GAIN").or_else(|| r128("R128_TRACK_GAIN")),
+            track_peak: peak("REPLAYGAIN_TRACK_PEAK"),
+            album_gain: gain("REPLAYGAIN_ALBUM_GAIN").or_else(|| r128("R128_ALBUM_GAIN")),
+            album_peak: peak("REPLAYGAIN_ALBUM_PEAK"),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.track_gain.is_none() && self.album_gain.is_none()
+    }
+
+    /// The gain to apply in `mode`, taking the other one when the file only has one of them. It is
+    /// held down so the tagged peak does not clip.
+    pub fn gain_db(&self, mode: ReplayGainMode) -> f32 {
+        let track = self.track_gain.map(|gain| (gain, self.track_peak));
+        let album = self.album_gain.map(|gain| (gain, self.album_peak));
+        let selected = match mode {
+            ReplayGainMode::Off => None,
+            ReplayGainMode::Track => track.or(album),
+            ReplayGainMode::Album => album.or(track),
+        };
+        match selected {
+            Some((gain, Some(peak))) if peak > 0.0 => gain.min(-20.0 * peak.log10()),
+            Some((gain, _)) => gain,
+            None => 0.0,
         }
     }
 }
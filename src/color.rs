@@ -0,0 +1,131 @@
+use ffmpeg_next::{
+    self as ffmpeg,
+    color::{ Range, Space, TransferCharacteristic },
+    format::Pixel,
+    frame::Video,
+    software::scaling,
+};
+use sdl3_sys::pixels::SDL_Colorspace;
+
+/// How a frame's YUV values turn into colours. Streams often leave the matrix and range out, the
+/// gaps are filled in the way most players do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Colorimetry {
+    pub space: Space,
+    pub full_range: bool,
+    pub transfer: TransferCharacteristic,
+}
+
+impl Colorimetry {
+    pub fn of(frame: &Video) -> Colorimetry {
+        let space = match frame.color_space() {
+            // HD and up is BT.709 unless it says otherwise, SD is BT.601
+            Space::Unspecified | Space::Reserved if frame.height() >= 720 => Space::BT709,
+            Space::Unspecified | Space::Reserved => Space::BT470BG,
+            space => space,
+        };
+        let full_range = match frame.color_range() {
+            Range::JPEG => true,
+            Range::MPEG => false,
+            // The J formats are the full range variants
+            Range::Unspecified =>
+                matches!(
+                    frame.format(),
+                    Pixel::YUVJ420P | Pixel::YUVJ422P | Pixel::YUVJ444P | Pixel::YUVJ440P
+                ),
+        };
+        Colorimetry { space, full_range, transfer: frame.color_transfer_characteristic() }
+    }
+
+    /// PQ (HDR10, Dolby Vision's base layer) or HLG, which look washed out when shown as SDR.
+    pub fn is_hdr(&self) -> bool {
+        matches!(
+            self.transfer,
+            TransferCharacteristic::SMPTE2084 | TransferCharacteristic::ARIB_STD_B67
+        )
+    }
+
+    // swscale's name for the matrix
+    fn sws_colorspace(&self) -> i32 {
+        (match self.space {
+            Space::BT709 => ffmpeg::ffi::SWS_CS_ITU709,
+            Space::FCC => ffmpeg::ffi::SWS_CS_FCC,
+            Space::SMPTE240M => ffmpeg::ffi::SWS_CS_SMPTE240M,
+            Space::BT2020NCL | Space::BT2020CL => ffmpeg::ffi::SWS_CS_BT2020,
+            _ => ffmpeg::ffi::SWS_CS_ITU601,
+        }) as i32
+    }
+
+    /// Sets `context` up to read frames with this colorimetry, the RGB side is full range. Left
+    /// as is, swscale takes everything for limited range BT.601.
+    pub fn apply_to_scaler(&self, context: &mut scaling::Context) {
+        unsafe {
+            let coefficients = ffmpeg::ffi::sws_getCoefficients(self.sws_colorspace());
+            let result = ffmpeg::ffi::sws_setColorspaceDetails(
+                context.as_mut_ptr(),
+                coefficients,
+                self.full_range as i32,
+                coefficients,
+                1,
+                0,
+                1 << 16,
+                1 << 16
+            );
+            if result < 0 {
+                println!("unable to set the scaler up for {:?}", self);
+            }
+        }
+    }
+
+    /// The colorspace a YUV texture needs to show the frames as they are. `None` for the matrices
+    /// SDL does not know, those frames go through swscale.
+    pub fn sdl_colorspace(&self) -> Option<SDL_Colorspace> {
+        match (self.space, self.full_range) {
+            (Space::BT709, false) => Some(SDL_Colorspace::BT709_LIMITED),
+            (Space::BT709, true) => Some(SDL_Colorspace::BT709_FULL),
+            (Space::BT470BG | Space::SMPTE170M, false) => Some(SDL_Colorspace::BT601_LIMITED),
+            (Space::BT470BG | Space::SMPTE170M, true) => Some(SDL_Colorspace::BT601_FULL),
+            (Space::BT2020NCL, false) => Some(SDL_Colorspace::BT2020_LIMITED),
+            (Space::BT2020NCL, true) => Some(SDL_Colorspace::BT2020_FULL),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tonemap {
+    // HDR frames are shown as they are
+    Off,
+    // Filmic curve, keeps contrast in the highlights
+    Hable,
+    // Leaves the colours below the peak mostly alone
+    Mobius,
+    Reinhard,
+}
+
+impl Tonemap {
+    /// Whether this FFmpeg has the filters `filter` needs, zscale is only there when built with
+    /// zimg.
+    pub fn available() -> bool {
+        ffmpeg::filter::find("zscale").is_some() && ffmpeg::filter::find("tonemap").is_some()
+    }
+
+    /// Converts PQ/HLG frames to SDR BT.709 with zscale, so it needs an FFmpeg built with zimg.
+    /// `None` when tonemapping is off.
+    pub fn filter(&self) -> Option<String> {
+        let curve = match self {
+            Tonemap::Off => {
+                return None;
+            }
+            Tonemap::Hable => "hable",
+            Tonemap::Mobius => "mobius",
+            Tonemap::Reinhard => "reinhard",
+        };
+        Some(
+            format!(
+                "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap={}:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p",
+                curve
+            )
+        )
+    }
+}
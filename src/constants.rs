@@ -20,6 +20,19 @@ impl ConvFormat<AudioFormat> for Sample {
     }
 }
 
+// Packed, the layout SDL streams take
+impl ConvFormat<Sample> for AudioFormat {
+    fn convert(&self) -> Sample {
+        match self {
+            AudioFormat::U8 => Sample::U8(Type::Packed),
+            AudioFormat::S16LE => Sample::I16(Type::Packed),
+            AudioFormat::S32LE => Sample::I32(Type::Packed),
+            AudioFormat::F32LE => Sample::F32(Type::Packed),
+            _ => Sample::None,
+        }
+    }
+}
+
 impl ConvFormat<PixelFormat> for Pixel {
     fn convert(&self) -> PixelFormat {
         match self {
@@ -36,9 +49,10 @@ impl ConvFormat<PixelFormat> for Pixel {
             Pixel::YUYV422 => PixelFormat::YUY2,
             Pixel::UYVY422 => PixelFormat::UYVY,
             Pixel::YVYU422 => PixelFormat::YVYU,
-            Pixel::RGB32 => PixelFormat::RGB332,
-            Pixel::YUV420P => PixelFormat::UYVY,
-            Pixel::YUV422P => PixelFormat::YUY2,
+            // Native endian ARGB words on both sides
+            Pixel::RGB32 => PixelFormat::ARGB8888,
+            // Full range 4:2:0 has the same layout, the range only matters for the colour conversion
+            Pixel::YUV420P | Pixel::YUVJ420P => PixelFormat::IYUV,
             _ => PixelFormat::UNKNOWN,
         }
     }
@@ -1,4 +1,4 @@
-use ffmpeg_next::{ Stream, codec::Parameters };
+use ffmpeg_next::{ Stream, codec::Parameters, format::stream::Disposition };
 use sdl3::audio::AudioSpec;
 
 use crate::{ constants::ConvFormat, core::StreamInfo };
@@ -38,6 +38,7 @@ impl ConvFormat<StreamInfo> for Stream<'_> {
             fps,
             audio_spec: spec,
             index: self.index() as i16,
+            attached_pic: self.disposition().contains(Disposition::ATTACHED_PIC),
         }
     }
 }
@@ -72,6 +73,7 @@ impl ConvFormat<StreamInfo> for Parameters {
             fps,
             audio_spec: spec,
             index: 0,
+            attached_pic: false,
         }
     }
 }
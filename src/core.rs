@@ -1,23 +1,43 @@
 use ffmpeg_next::{
     self as ffmpeg,
-    codec::{ Context, Parameters },
-    decoder::Subtitle,
+    codec::{ Context, Parameters, threading },
     frame::{ self, Audio, Video },
     media::Type,
-    Frame,
+    rescale,
     Rational,
-    // Subtitle,
+    Rescale,
 };
-use sdl3::audio::AudioSpec;
+use sdl3::audio::{ AudioFormat, AudioSpec };
 use std::{
-    sync::{ Arc, Mutex, RwLock, mpsc::{ self, Receiver, Sender, SyncSender } },
+    mem,
+    sync::{
+        Arc,
+        Mutex,
+        RwLock,
+        atomic::{ AtomicBool, AtomicU32, Ordering },
+        mpsc::{ self, Receiver, Sender, SyncSender, TryRecvError, TrySendError },
+    },
     thread::{ self, JoinHandle },
+    time::Duration,
 };
 
-use ffmpeg::{ Packet, Stream, format::input };
+use ffmpeg::{ Packet, Stream, filter, format::input };
 
 use crate::{
+    color::{ Colorimetry, Tonemap },
     constants::ConvFormat,
+    filters::{
+        AudioResampler,
+        Deinterlace,
+        Orientation,
+        drain_audio_filter,
+        drain_video_filter,
+        effects_graph,
+        run_audio_filter,
+        run_video_filter,
+        video_filter,
+    },
+    subtitles::{ SubtitleCue, parameters_size },
     utils::{
         calculate_tpf_from_time_base,
         height_from_ar,
@@ -26,38 +46,114 @@ use crate::{
         MDecodeOptions,
         MediaInfo,
         Range,
+        StreamSelection,
     },
+    view::VideoView,
 };
 
+pub const MILLIS: Rational = Rational(1, 1000);
+
 pub struct MPlayerCore {
     // pub packet_queue: Arc<RwLock<VecDeque<(Packet, PacketMarker)>>>,
     pub look_range: Range,
     pub video: Option<DecodeThread<Video>>,
     pub audio: Option<DecodeThread<Audio>>,
-    pub subtitle: Option<DecodeThread<Frame>>,
+    pub subtitle: Option<DecodeThread<SubtitleCue>>,
     pub config: &'static MDecodeOptions,
     pub media_info: Option<MediaInfo>,
     pub officer_he_has_a_gun: Option<SyncSender<PacketDistributorCommand>>,
     pub what_gun: Option<JoinHandle<()>>,
     pub has_media: bool,
+    // Set by the media thread once a seek has been issued, in AV_TIME_BASE units.
+    // The player takes it to drop its buffered frames and rebase its clock.
+    pub pending_seek: Option<i64>,
+    // Set by the media thread when a seek could not be done, the player takes it to stop
+    // waiting for one.
+    pub seek_failed: bool,
+    // Decoders of the next playlist entry, opened ahead of time for a gapless switch
+    pub next_video: Option<DecodeThread<Video>>,
+    pub next_audio: Option<DecodeThread<Audio>>,
+    pub next_subtitle: Option<DecodeThread<SubtitleCue>>,
 }
 
 pub enum MediaThreadCommand {
     Play,
     Pause,
+    // Target position in AV_TIME_BASE (microsecond) units.
     Seek(i64),
+    // Swap the audio decoder for one on another stream of the same file
+    SelectAudio(StreamSelection),
+    SelectSubtitle(Option<StreamSelection>),
     Exit,
 }
 
+// Positions are demuxer read positions in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MediaThreadStatus {
     Paused(u32),
     Seeking(/*From*/ u32, /*To*/ u32),
     Playing(u32),
     Stopped,
 }
+
+#[derive(Debug, Clone)]
+pub struct MediaThreadReport {
+    pub status: MediaThreadStatus,
+    // Container duration in milliseconds, if known
+    pub duration: Option<u32>,
+    // Span read by the demuxer since the start or the last seek, in milliseconds
+    pub buffered: Range,
+    // The demuxer ran out of packets and told the decoders to drain, what is left is in the
+    // decoders and the player. The thread stays around so a seek can pick the file back up.
+    pub eof: bool,
+    // Streams are set up and the decoders are running
+    pub opened: bool,
+    pub tracks: Vec<TrackInfo>,
+    // Stream indices of the tracks being played
+    pub video_track: Option<usize>,
+    pub audio_track: Option<usize>,
+    pub subtitle_track: Option<usize>,
+    pub media_info: Option<MediaInfo>,
+}
+
+impl MediaThreadReport {
+    /// The file could not be opened, or it has no audio or video to play.
+    pub fn failed(&self) -> bool {
+        if self.opened {
+            self.video_track.is_none() && self.audio_track.is_none()
+        } else {
+            self.status == MediaThreadStatus::Stopped
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackInfo {
+    pub index: usize,
+    pub kind: Type,
+    pub language: Option<String>,
+    pub codec: String,
+}
+
 pub struct MediaThread {
     command_tx: Sender<MediaThreadCommand>,
-    pub status: Arc<RwLock<MediaThreadStatus>>,
+    pub status: Arc<RwLock<MediaThreadReport>>,
+    seek_serial: Arc<AtomicU32>,
+    playback_speed: Arc<RwLock<f64>>,
+    // Effects chain in libavfilter syntax run by the audio decoder
+    audio_filters: Arc<RwLock<Option<String>>>,
+    // Same for the video decoder, starts out as `MDecodeOptions::video_filters`
+    video_filters: Arc<RwLock<Option<String>>>,
+    deinterlace: Arc<RwLock<Deinterlace>>,
+    // Set once the video decoder came across an interlaced frame
+    interlaced: Arc<AtomicBool>,
+    // Replaces the orientation from the stream's metadata when set
+    rotation: Arc<RwLock<Option<u32>>>,
+    tonemap: Arc<RwLock<Tonemap>>,
+    // Set while the decoders belong in `next_video`/`next_audio`
+    preload: Arc<AtomicBool>,
+    // Size the video decoder scales to, `None` until the player asks for one
+    output_size: Arc<RwLock<Option<(u32, u32)>>>,
     handle: JoinHandle<()>,
 }
 
@@ -79,35 +175,133 @@ impl MPlayerCore {
             officer_he_has_a_gun: None,
             what_gun: None,
             look_range: Range::new(10, 50),
+            pending_seek: None,
+            seek_failed: false,
+            next_video: None,
+            next_audio: None,
+            next_subtitle: None,
         }
     }
 
+    /// Opens `path` and starts demuxing it on its own thread. With `preload` the decoders go to
+    /// `next_video`/`next_audio` and only fill their buffers until the player switches over.
     pub fn open_media(
         path: String,
         decode_options: Option<MDecodeOptions>,
-        mutex: Arc<Mutex<MPlayerCore>>
+        mutex: Arc<Mutex<MPlayerCore>>,
+        preload: bool
     ) -> MediaThread {
         let (command_tx, command_rx) = mpsc::channel::<MediaThreadCommand>();
-        let status = Arc::new(RwLock::new(MediaThreadStatus::Paused(0)));
+        let status = Arc::new(
+            RwLock::new(MediaThreadReport {
+                status: MediaThreadStatus::Paused(0),
+                duration: None,
+                buffered: Range::new(0, 0),
+                eof: false,
+                opened: false,
+                tracks: Vec::new(),
+                video_track: None,
+                audio_track: None,
+                subtitle_track: None,
+                media_info: None,
+            })
+        );
+        let seek_serial = Arc::new(AtomicU32::new(0));
+        let playback_speed = Arc::new(RwLock::new(1.0));
+        let audio_filters = Arc::new(RwLock::new(None));
+        let video_filters = Arc::new(
+            RwLock::new(decode_options.as_ref().and_then(|options| options.video_filters.clone()))
+        );
+        let deinterlace = Arc::new(
+            RwLock::new(
+                decode_options
+                    .as_ref()
+                    .map_or(MDecodeOptions::default().deinterlace, |options| options.deinterlace)
+            )
+        );
+        let interlaced = Arc::new(AtomicBool::new(false));
+        let rotation = Arc::new(
+            RwLock::new(decode_options.as_ref().and_then(|options| options.rotation))
+        );
+        let tonemap = Arc::new(
+            RwLock::new(decode_options.as_ref().map_or(Tonemap::Off, |options| options.tonemap))
+        );
+        let preload = Arc::new(AtomicBool::new(preload));
+        let output_size = Arc::new(RwLock::new(None));
+        let c_seek_serial = Arc::clone(&seek_serial);
+        let c_playback_speed = Arc::clone(&playback_speed);
+        let c_audio_filters = Arc::clone(&audio_filters);
+        let c_video_filters = Arc::clone(&video_filters);
+        let c_deinterlace = Arc::clone(&deinterlace);
+        let c_interlaced = Arc::clone(&interlaced);
+        let c_rotation = Arc::clone(&rotation);
+        let c_tonemap = Arc::clone(&tonemap);
+        let c_preload = Arc::clone(&preload);
+        let c_output_size = Arc::clone(&output_size);
+        let c_status = Arc::clone(&status);
         let handle = thread::Builder
             ::new()
             .name("main_media".to_string())
             .spawn(move || {
+                let seek_serial = c_seek_serial;
+                let playback_speed = c_playback_speed;
+                let audio_filters = c_audio_filters;
+                let video_filters = c_video_filters;
+                let deinterlace = c_deinterlace;
+                let interlaced = c_interlaced;
+                let rotation = c_rotation;
+                let tonemap = c_tonemap;
+                let preload = c_preload;
+                let output_size = c_output_size;
+                let status = c_status;
                 if let Ok(mut input_ctx) = input(&path) {
                     let decode_options = decode_options.unwrap_or_default();
-                    let _ = print_context_data(&input_ctx);
+                    let media_info = MediaInfo::get_media_info_from_input(&input_ctx);
+                    if !preload.load(Ordering::SeqCst) && let Ok(mut lock) = mutex.lock() {
+                        lock.media_info = Some(media_info.clone());
+                    }
+                    if let Ok(mut report) = status.write() {
+                        report.status = MediaThreadStatus::Playing(0);
+                        report.media_info = Some(media_info);
+                        report.tracks = input_ctx
+                            .streams()
+                            .map(|stream| TrackInfo {
+                                index: stream.index(),
+                                kind: stream.parameters().medium(),
+                                language: stream
+                                    .metadata()
+                                    .get("language")
+                                    .map(|language| language.to_string()),
+                                codec: format!("{:?}", stream.parameters().id()),
+                            })
+                            .collect();
+                        if input_ctx.duration() > 0 {
+                            report.duration = Some(
+                                input_ctx.duration().rescale(rescale::TIME_BASE, MILLIS) as u32
+                            );
+                        }
+                    }
+                    let mut paused = false;
+                    let mut read_position = 0;
+                    // Last status written to the report, which is only written when it changes
+                    let mut reported_status = MediaThreadStatus::Playing(0);
+                    let _ = print_context_data(&input_ctx, decode_options.threading());
                     let mut video_tx = None;
                     let mut audio_tx = None;
-                    // let mut subtitle_tx = None;
+                    // The decoders' drained flags, reset on a seek
+                    let mut video_drained = None;
+                    let mut audio_drained = None;
+                    let mut subtitle_tx = None;
                     let (mut video_marker, mut audio_marker, mut subtitle_marker): (
                         Option<PacketMarker>,
                         Option<PacketMarker>,
                         Option<PacketMarker>,
                     ) = (None, None, None);
                     if
-                        let Some(video_stream) = input_ctx
-                            .streams()
-                            .best(ffmpeg_next::media::Type::Video)
+                        let Some(video_stream) = decode_options.video_stream.find(
+                            &input_ctx,
+                            Type::Video
+                        )
                     {
                         let (p_tx_video, p_rx_video) = mpsc::sync_channel(1000);
                         video_tx = Some(p_tx_video);
@@ -122,113 +316,375 @@ impl MPlayerCore {
                                         buffer_capacity: 24,
                                         time_base: video_stream.time_base(),
                                     }),
-                                    Mutex::new(decode_options)
+                                    Mutex::new(decode_options.clone()),
+                                    Arc::clone(&seek_serial),
+                                    Arc::clone(&output_size),
+                                    Arc::clone(&video_filters),
+                                    Arc::clone(&deinterlace),
+                                    Arc::clone(&interlaced),
+                                    Arc::clone(&rotation),
+                                    Arc::clone(&tonemap)
                                 )
                             ).unwrap();
-                            v.stream_info = video_stream.convert();
-                            lock.video = Some(v);
-                            lock.has_media = true;
+                            video_drained = Some(Arc::clone(&v.drained));
+                            if preload.load(Ordering::SeqCst) {
+                                lock.next_video = Some(v);
+                            } else {
+                                lock.video = Some(v);
+                                lock.has_media = true;
+                            }
                         }
                     }
 
                     if
-                        let Some(audio_stream) = input_ctx
-                            .streams()
-                            .best(ffmpeg_next::media::Type::Audio)
+                        let Some(audio_stream) = decode_options.audio_stream.find(
+                            &input_ctx,
+                            Type::Audio
+                        )
                     {
-                        let (p_tx_audio, p_rx_audio) = mpsc::sync_channel(1000);
+                        let (p_tx_audio, a) = spawn_audio_decoder(
+                            &audio_stream,
+                            &seek_serial,
+                            &playback_speed,
+                            &audio_filters,
+                            &decode_options
+                        );
                         audio_marker = Some(audio_stream.convert());
                         audio_tx = Some(p_tx_audio);
+                        audio_drained = Some(Arc::clone(&a.drained));
                         if let Ok(mut lock) = mutex.lock() {
-                            let mut a = DecodeThread::<Audio>::spawn(
-                                audio_stream.parameters(),
-                                p_rx_audio,
-                                Some("audio".to_string()),
-                                Some(ThreadConfig {
-                                    buffer_capacity: 40,
-                                    time_base: audio_stream.time_base(),
-                                })
-                            );
-                            a.stream_info = audio_stream.convert();
-                            lock.audio = Some(a);
-                            lock.has_media = true;
+                            if preload.load(Ordering::SeqCst) {
+                                lock.next_audio = Some(a);
+                            } else {
+                                lock.audio = Some(a);
+                                lock.has_media = true;
+                            }
+                        }
+                    }
+
+                    // Bitmap subtitles are positioned on the video's picture
+                    let video_size = video_marker
+                        .as_ref()
+                        .and_then(|marker| input_ctx.stream(marker.stream_index))
+                        .map_or((0, 0), |stream| parameters_size(&stream.parameters()));
+                    if
+                        let Some(ref selection) = decode_options.subtitle_stream &&
+                        let Some(subtitle_stream) = selection.find(&input_ctx, Type::Subtitle)
+                    {
+                        let (p_tx_subtitle, s) = spawn_subtitle_decoder(
+                            &subtitle_stream,
+                            video_size,
+                            &seek_serial
+                        );
+                        subtitle_marker = Some(subtitle_stream.convert());
+                        subtitle_tx = Some(p_tx_subtitle);
+                        if let Ok(mut lock) = mutex.lock() {
+                            if preload.load(Ordering::SeqCst) {
+                                lock.next_subtitle = Some(s);
+                            } else {
+                                lock.subtitle = Some(s);
+                            }
                         }
                     }
 
-                    // if let Some(subtitle_stream) = input_ctx.streams().best(Type::Subtitle) {
-                    //     let (p_tx_subtitle, p_rx_subtitle) = mpsc::sync_channel(1000);
-                    //     subtitle_marker = Some(subtitle_stream.convert());
-                    //     subtitle_tx = Some(p_tx_subtitle);
-                    //     if let Ok(mut lock) = mutex.lock() {
-                    //         lock.subtitle = Some(
-                    //             DecodeThread::<Frame>::subtitle(
-                    //                 subtitle_stream.parameters(),
-                    //                 p_rx_subtitle,
-                    //                 Some(ThreadConfig {
-                    //                     buffer_capacity: 100,
-                    //                     time_base: Rational(0, 1),
-                    //                 })
-                    //             )
-                    //         );
-                    //     }
-                    // }
-
-                    while let Some((stream, packet)) = input_ctx.packets().next() {
-                        if let Ok(command) = command_rx.try_recv() {
+                    if let Ok(mut report) = status.write() {
+                        report.opened = true;
+                        report.video_track = video_marker.as_ref().map(|marker| marker.stream_index);
+                        report.audio_track = audio_marker.as_ref().map(|marker| marker.stream_index);
+                        report.subtitle_track = subtitle_marker
+                            .as_ref()
+                            .map(|marker| marker.stream_index);
+                    }
+                    let mut at_eof = false;
+                    loop {
+                        let mut seek_to = None;
+                        let mut should_exit = false;
+                        let mut commands = Vec::new();
+                        // Nothing left to read, wait for a seek or the exit
+                        if at_eof {
+                            match command_rx.recv() {
+                                Ok(command) => commands.push(command),
+                                Err(_) => {
+                                    should_exit = true;
+                                }
+                            }
+                        }
+                        loop {
+                            match command_rx.try_recv() {
+                                Ok(command) => commands.push(command),
+                                Err(TryRecvError::Empty) => {
+                                    break;
+                                }
+                                // The player dropped its handle without asking
+                                Err(TryRecvError::Disconnected) => {
+                                    should_exit = true;
+                                    break;
+                                }
+                            }
+                        }
+                        for command in commands {
                             match command {
-                                MediaThreadCommand::Play => {}
+                                // Only matters for network streams, local files are held back
+                                // by the packet channels filling up while the player is paused.
+                                MediaThreadCommand::Play => {
+                                    let _ = input_ctx.play();
+                                    paused = false;
+                                }
                                 MediaThreadCommand::Pause => {
-                                    if let Ok(MediaThreadCommand::Play) = command_rx.recv() {
-                                        continue;
-                                    }
+                                    let _ = input_ctx.pause();
+                                    paused = true;
                                 }
+                                // Only the latest target is worth seeking to.
                                 MediaThreadCommand::Seek(to) => {
-                                    // let _ = input_ctx.seek(to, ..);
+                                    seek_to = Some(to);
                                 }
-                                MediaThreadCommand::Exit => {
-                                    if let Some(ref mut vid_tx) = video_tx {
-                                        let _ = vid_tx.send(ThreadData::Kill);
+                                MediaThreadCommand::SelectAudio(selection) => {
+                                    let Some(audio_stream) = selection.find(
+                                        &input_ctx,
+                                        Type::Audio
+                                    ) else {
+                                        continue;
+                                    };
+                                    if
+                                        audio_marker
+                                            .as_ref()
+                                            .is_some_and(
+                                                |marker| marker.stream_index == audio_stream.index()
+                                            )
+                                    {
+                                        continue;
                                     }
-                                    if let Some(ref mut audio_tx) = audio_tx {
-                                        let _ = audio_tx.send(ThreadData::Kill);
+                                    let (p_tx_audio, a) = spawn_audio_decoder(
+                                        &audio_stream,
+                                        &seek_serial,
+                                        &playback_speed,
+                                        &audio_filters,
+                                        &decode_options
+                                    );
+                                    // The old decoder goes away along with its channel, packets
+                                    // of its stream are not routed anywhere from here on.
+                                    if let Some(ref mut aud_tx) = audio_tx {
+                                        let _ = aud_tx.send(ThreadData::Kill);
                                     }
-                                    // if let Some(ref mut subtitle_tx) = subtitle_tx {
-                                    //     let _ = subtitle_tx.send(ThreadData::Kill);
-                                    // }
-                                    break;
+                                    audio_marker = Some(audio_stream.convert());
+                                    audio_tx = Some(p_tx_audio);
+                                    audio_drained = Some(Arc::clone(&a.drained));
+                                    if let Ok(mut lock) = mutex.lock() {
+                                        if preload.load(Ordering::SeqCst) {
+                                            lock.next_audio = Some(a);
+                                        } else {
+                                            lock.audio = Some(a);
+                                            lock.has_media = true;
+                                        }
+                                    }
+                                    if let Ok(mut report) = status.write() {
+                                        report.audio_track = Some(audio_stream.index());
+                                    }
+                                }
+                                MediaThreadCommand::SelectSubtitle(selection) => {
+                                    let subtitle_stream = match selection {
+                                        // Subtitles off
+                                        None => None,
+                                        Some(selection) =>
+                                            match selection.find(&input_ctx, Type::Subtitle) {
+                                                Some(stream) => Some(stream),
+                                                None => {
+                                                    continue;
+                                                }
+                                            }
+                                    };
+                                    if
+                                        subtitle_marker.as_ref().map(|marker| marker.stream_index) ==
+                                        subtitle_stream.as_ref().map(|stream| stream.index())
+                                    {
+                                        continue;
+                                    }
+                                    if let Some(ref mut sub_tx) = subtitle_tx {
+                                        let _ = sub_tx.send(ThreadData::Kill);
+                                    }
+                                    subtitle_marker = None;
+                                    subtitle_tx = None;
+                                    let mut s = None;
+                                    if let Some(subtitle_stream) = subtitle_stream {
+                                        let (p_tx_subtitle, decoder) = spawn_subtitle_decoder(
+                                            &subtitle_stream,
+                                            video_size,
+                                            &seek_serial
+                                        );
+                                        subtitle_marker = Some(subtitle_stream.convert());
+                                        subtitle_tx = Some(p_tx_subtitle);
+                                        s = Some(decoder);
+                                    }
+                                    if let Ok(mut lock) = mutex.lock() {
+                                        if preload.load(Ordering::SeqCst) {
+                                            lock.next_subtitle = s;
+                                        } else {
+                                            lock.subtitle = s;
+                                        }
+                                    }
+                                    if let Ok(mut report) = status.write() {
+                                        report.subtitle_track = subtitle_marker
+                                            .as_ref()
+                                            .map(|marker| marker.stream_index);
+                                    }
+                                }
+                                MediaThreadCommand::Exit => {
+                                    should_exit = true;
+                                }
+                            }
+                        }
+                        if should_exit {
+                            break;
+                        }
+                        if let Some(to) = seek_to {
+                            let to_ms = to.max(0).rescale(rescale::TIME_BASE, MILLIS) as u32;
+                            // Land on the closest keyframe at or before the target.
+                            let seeked = input_ctx.seek(to, ..to);
+                            if let Err(err) = &seeked {
+                                println!("seek to {} failed: {:?}", to, err);
+                            }
+                            reported_status = match seeked {
+                                Ok(_) => MediaThreadStatus::Seeking(read_position, to_ms),
+                                // Reading goes on from where it was
+                                Err(_) if paused => MediaThreadStatus::Paused(read_position),
+                                Err(_) => MediaThreadStatus::Playing(read_position),
+                            };
+                            if let Ok(mut report) = status.write() {
+                                report.status = reported_status;
+                            }
+                            // The decoders have been skipping packets since the serial was
+                            // bumped by MediaThread::seek, this lets them resume even if the
+                            // seek itself failed.
+                            let serial = seek_serial.load(Ordering::SeqCst);
+                            for drained in video_drained.iter().chain(audio_drained.iter()) {
+                                drained.store(false, Ordering::SeqCst);
+                            }
+                            at_eof = false;
+                            if let Some(ref mut vid_tx) = video_tx {
+                                let _ = vid_tx.send(ThreadData::Flush(serial));
+                            }
+                            if let Some(ref mut aud_tx) = audio_tx {
+                                let _ = aud_tx.send(ThreadData::Flush(serial));
+                            }
+                            if let Some(ref mut sub_tx) = subtitle_tx {
+                                let _ = sub_tx.send(ThreadData::Flush(serial));
+                            }
+                            if let Ok(mut lock) = mutex.lock() {
+                                match seeked {
+                                    Ok(_) => lock.pending_seek = Some(to),
+                                    Err(_) => lock.seek_failed = true,
+                                }
+                            }
+                            if seeked.is_ok() && let Ok(mut report) = status.write() {
+                                report.buffered = Range::new(to_ms, to_ms);
+                                report.eof = false;
+                            }
+                        }
+
+                        // The decoders got their Eof already, only a seek gives them more to do
+                        if at_eof {
+                            continue;
+                        }
+
+                        let Some((stream, packet)) = input_ctx.packets().next() else {
+                            // Get the decoders to hand over the frames they are holding on to
+                            if let Some(ref mut vid_tx) = video_tx {
+                                let _ = vid_tx.send(ThreadData::Eof);
+                            }
+                            if let Some(ref mut aud_tx) = audio_tx {
+                                let _ = aud_tx.send(ThreadData::Eof);
+                            }
+                            if let Ok(mut report) = status.write() {
+                                report.eof = true;
+                                // A seek past the end never reaches its target
+                                if let MediaThreadStatus::Seeking(..) = reported_status {
+                                    reported_status = if paused {
+                                        MediaThreadStatus::Paused(read_position)
+                                    } else {
+                                        MediaThreadStatus::Playing(read_position)
+                                    };
+                                    report.status = reported_status;
                                 }
                             }
+                            at_eof = true;
+                            continue;
+                        };
+                        let stream_index = stream.index();
+                        if let Some(pts) = packet.pts().or(packet.dts()) {
+                            read_position = pts.max(0).rescale(stream.time_base(), MILLIS) as u32;
+                        }
+                        let current_status = match reported_status {
+                            // Front ends get to see the seek until the demuxer reaches its target
+                            MediaThreadStatus::Seeking(from, to) if read_position < to => {
+                                MediaThreadStatus::Seeking(from, to)
+                            }
+                            _ if paused => MediaThreadStatus::Paused(read_position),
+                            _ => MediaThreadStatus::Playing(read_position),
+                        };
+                        // The streams' packets come interleaved, the position only counts when it
+                        // moves on. Most packets leave the report, and its lock, alone.
+                        let changed = match (reported_status, current_status) {
+                            (MediaThreadStatus::Playing(before), MediaThreadStatus::Playing(now)) |
+                            (MediaThreadStatus::Paused(before), MediaThreadStatus::Paused(now)) =>
+                                now > before,
+                            (before, now) => before != now,
+                        };
+                        if changed && let Ok(mut report) = status.write() {
+                            report.status = current_status;
+                            report.buffered.max = report.buffered.max.max(read_position);
+                            reported_status = current_status;
                         }
                         if let Some(marker) = &video_marker && let Some(ref mut vid_tx) = video_tx {
-                            if marker.stream_index == stream.index() {
+                            if marker.stream_index == stream_index {
                                 let _ = vid_tx.send(ThreadData::Packet(packet));
                                 continue;
                             }
                         }
                         if let Some(marker) = &audio_marker && let Some(ref mut aud_tx) = audio_tx {
-                            if marker.stream_index == stream.index() {
+                            if marker.stream_index == stream_index {
                                 let _ = aud_tx.send(ThreadData::Packet(packet));
                                 continue;
                             }
                         }
-                        // if
-                        //     let Some(marker) = &subtitle_marker &&
-                        //     let Some(ref mut sub_tx) = subtitle_tx
-                        // {
-                        //     if marker.stream_index == stream.index() {
-                        //     }
-                        //     {
-                        //         let _ = sub_tx.send(ThreadData::Packet(packet));
-                        //         continue;
-                        //     }
-                        // }
+                        if
+                            let Some(marker) = &subtitle_marker &&
+                            let Some(ref mut sub_tx) = subtitle_tx
+                        {
+                            if marker.stream_index == stream_index {
+                                let _ = sub_tx.send(ThreadData::Packet(packet));
+                                continue;
+                            }
+                        }
+                    }
+                    // However the loop ended, the decoders go down with it
+                    if let Some(ref mut vid_tx) = video_tx {
+                        let _ = vid_tx.send(ThreadData::Kill);
+                    }
+                    if let Some(ref mut aud_tx) = audio_tx {
+                        let _ = aud_tx.send(ThreadData::Kill);
+                    }
+                    if let Some(ref mut sub_tx) = subtitle_tx {
+                        let _ = sub_tx.send(ThreadData::Kill);
                     }
                 }
+                if let Ok(mut report) = status.write() {
+                    report.status = MediaThreadStatus::Stopped;
+                }
             })
             .unwrap();
         MediaThread {
             command_tx,
-            status: status.clone(),
+            status,
+            seek_serial,
+            playback_speed,
+            audio_filters,
+            video_filters,
+            deinterlace,
+            interlaced,
+            rotation,
+            tonemap,
+            preload,
+            output_size,
             handle,
         }
     }
@@ -265,13 +721,259 @@ pub struct ThreadConfig {
 
 pub enum ThreadData {
     Packet(Packet),
+    // Drop the decoder state, every packet after this belongs to the given seek serial.
+    Flush(u32),
+    // No more packets, output whatever the decoder still holds.
+    Eof,
     Kill,
 }
 
+// Pushes decoded output to the player without blocking past a seek: if the serial moves on
+// while the channel is full, the (now stale) data is dropped instead.
+fn send_output<T>(output_tx: &SyncSender<T>, data: T, seek_serial: &AtomicU32, serial: u32) {
+    let mut data = data;
+    loop {
+        if seek_serial.load(Ordering::SeqCst) != serial {
+            return;
+        }
+        match output_tx.try_send(data) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {
+                return;
+            }
+            Err(TrySendError::Full(returned)) => {
+                data = returned;
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+}
+
+// Deinterlacing goes first as it needs the fields as they were shot, then HDR frames are
+// tonemapped and the picture is turned upright for the user's filters. A rotation from the player
+// replaces the stream's orientation. Each stage is one entry, so a broken one can be left out.
+fn video_chain(
+    deinterlace: &RwLock<Deinterlace>,
+    interlaced: bool,
+    tonemap: &RwLock<Tonemap>,
+    hdr: bool,
+    stream_orientation: Orientation,
+    rotation: &RwLock<Option<u32>>,
+    video_filters: &RwLock<Option<String>>
+) -> Vec<String> {
+    let orientation = match rotation.read().ok().and_then(|rotation| *rotation) {
+        Some(rotation) => Orientation { rotation, ..Orientation::default() },
+        None => stream_orientation,
+    };
+    [
+        deinterlace
+            .read()
+            .ok()
+            .and_then(|deinterlace| deinterlace.filter(interlaced)),
+        tonemap
+            .read()
+            .ok()
+            .filter(|_| hdr)
+            .and_then(|tonemap| tonemap.filter()),
+        orientation.filter(),
+        video_filters.read().ok().and_then(|filters| filters.clone()),
+    ]
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+// The filter graph for `frame`, built again when the chain or the frames change. `None` without
+// filters, or when none of them set up, so the frames go on unfiltered.
+fn video_graph<'a>(
+    filter_graph: &'a mut Option<filter::Graph>,
+    filtered_for: &mut Option<(Vec<String>, ffmpeg::format::Pixel, u32, u32)>,
+    frame: &Video,
+    stages: Vec<String>,
+    time_base: Rational
+) -> Option<&'a mut filter::Graph> {
+    let key = (stages, frame.format(), frame.width(), frame.height());
+    if filtered_for.as_ref() != Some(&key) {
+        *filter_graph = build_video_graph(&key.0, frame, time_base);
+        *filtered_for = Some(key);
+    }
+    filter_graph.as_mut()
+}
+
+// Joins `stages` into one graph. When that does not set up, the stages that fail on their own
+// are dropped and the rest are tried again, so a bad custom filter or a missing zscale does not
+// also take the deinterlacing and the rotation with it.
+fn build_video_graph(
+    stages: &[String],
+    frame: &Video,
+    time_base: Rational
+) -> Option<filter::Graph> {
+    if stages.is_empty() {
+        return None;
+    }
+    let chain = stages.join(",");
+    let err = match video_filter(&chain, frame, time_base) {
+        Ok(graph) => {
+            return Some(graph);
+        }
+        Err(err) => err,
+    };
+    println!("unable to set up video filters \"{}\": {:?}", chain, err);
+    let working: Vec<&str> = stages
+        .iter()
+        .map(String::as_str)
+        .filter(|stage| video_filter(stage, frame, time_base).is_ok())
+        .collect();
+    if working.is_empty() || working.len() == stages.len() {
+        return None;
+    }
+    let chain = working.join(",");
+    println!("going on with \"{}\"", chain);
+    video_filter(&chain, frame, time_base)
+        .inspect_err(|err| {
+            println!("unable to set up video filters \"{}\": {:?}", chain, err)
+        })
+        .ok()
+}
+
+// Gives `scaled` the pixel aspect that makes it display with the shape `source` has
+fn set_sample_aspect(scaled: &mut Video, source: &Video) {
+    let Some(aspect) = VideoView::display_aspect(
+        source.width(),
+        source.height(),
+        source.aspect_ratio()
+    ) else {
+        return;
+    };
+    let sample_aspect = (aspect as f64) * (scaled.height() as f64) / (scaled.width().max(1) as f64);
+    unsafe {
+        (*scaled.as_mut_ptr()).sample_aspect_ratio = Rational::from(sample_aspect).into();
+    }
+}
+
+// The scaler for `frame`, built again when the input or the wanted size changed. Without a size
+// from the player the frame is scaled to `window_default_size`'s height. The YUV side is read
+// with the frame's own matrix and range.
+fn video_scaler<'a>(
+    scaling_context: &'a mut Option<ffmpeg::software::scaling::Context>,
+    scaled_for: &mut Option<(ffmpeg::format::Pixel, u32, u32, (u32, u32), Colorimetry)>,
+    frame: &Video,
+    options: &MDecodeOptions,
+    output_size: Option<(u32, u32)>
+) -> Option<&'a mut ffmpeg::software::scaling::Context> {
+    let (width, height) = output_size.unwrap_or_else(|| {
+        let height = options.window_default_size.1;
+        (width_from_ar(Rational(frame.width() as i32, frame.height() as i32), height), height)
+    });
+    let size = (width.max(1), height.max(1));
+    let colorimetry = Colorimetry::of(frame);
+    let key = (frame.format(), frame.width(), frame.height(), size, colorimetry);
+    if *scaled_for != Some(key) {
+        *scaling_context = ffmpeg::software::scaling::Context
+            ::get(
+                frame.format(),
+                frame.width(),
+                frame.height(),
+                options.pixel_format,
+                size.0,
+                size.1,
+                options.scaling_flag
+            )
+            .ok();
+        if let Some(context) = scaling_context.as_mut() {
+            colorimetry.apply_to_scaler(context);
+        }
+        *scaled_for = Some(key);
+    }
+    scaling_context.as_mut()
+}
+
+// Starts an audio decoder on `stream`, returning the channel its packets go to.
+fn spawn_audio_decoder(
+    stream: &Stream<'_>,
+    seek_serial: &Arc<AtomicU32>,
+    playback_speed: &Arc<RwLock<f64>>,
+    audio_filters: &Arc<RwLock<Option<String>>>,
+    decode_options: &MDecodeOptions
+) -> (SyncSender<ThreadData>, DecodeThread<Audio>) {
+    let (p_tx_audio, p_rx_audio) = mpsc::sync_channel(1000);
+    let mut a = DecodeThread::<Audio>::spawn(
+        stream.parameters(),
+        p_rx_audio,
+        Some("audio".to_string()),
+        Some(ThreadConfig {
+            buffer_capacity: 40,
+            time_base: stream.time_base(),
+        }),
+        Arc::clone(seek_serial),
+        Arc::clone(playback_speed),
+        Arc::clone(audio_filters),
+        decode_options.threading(),
+        decode_options.audio_output.clone()
+    );
+    a.stream_info = stream.convert();
+    (p_tx_audio, a)
+}
+
+// Starts a subtitle decoder on `stream`, returning the channel its packets go to.
+fn spawn_subtitle_decoder(
+    stream: &Stream<'_>,
+    video_size: (u32, u32),
+    seek_serial: &Arc<AtomicU32>
+) -> (SyncSender<ThreadData>, DecodeThread<SubtitleCue>) {
+    let (p_tx_subtitle, p_rx_subtitle) = mpsc::sync_channel(1000);
+    let subtitle_size = parameters_size(&stream.parameters());
+    let s = DecodeThread::<SubtitleCue>::spawn(
+        stream,
+        p_rx_subtitle,
+        Some("subtitle".to_string()),
+        Some(ThreadConfig {
+            buffer_capacity: 100,
+            time_base: stream.time_base(),
+        }),
+        if subtitle_size.0 > 0 && subtitle_size.1 > 0 { subtitle_size } else { video_size },
+        Arc::clone(seek_serial)
+    );
+    (p_tx_subtitle, s)
+}
+
+// Converts `frame` to the device spec when there is a resampler and sends what comes out
+fn send_audio(
+    output_tx: &SyncSender<Audio>,
+    resampler: &mut Option<AudioResampler>,
+    frame: Audio,
+    seek_serial: &AtomicU32,
+    serial: u32
+) {
+    let frame = match resampler {
+        Some(resampler) => resampler.run(&frame),
+        None => Some(frame),
+    };
+    if let Some(frame) = frame {
+        send_output(output_tx, frame, seek_serial, serial);
+    }
+}
+
+// Stamps a time-stretched frame with the media time it starts at and moves `next_pts` past it.
+fn stamp_media_pts(frame: &mut Audio, next_pts: &mut Option<f64>, speed: f64, time_base: Rational) {
+    if let Some(pts) = *next_pts {
+        frame.set_pts(Some(pts as i64));
+        let media_secs = ((frame.samples() as f64) * speed) / (frame.rate() as f64);
+        *next_pts = Some(pts + media_secs / f64::from(time_base));
+    }
+}
+
 pub struct DecodeThread<OutputType> {
     pub handle: JoinHandle<()>,
     pub output_rx: Receiver<OutputType>,
     pub stream_info: StreamInfo,
+    // Set once everything up to the end of the stream has been sent to `output_rx`
+    pub drained: Arc<AtomicBool>,
+}
+
+impl<OutputType> DecodeThread<OutputType> {
+    pub fn is_drained(&self) -> bool {
+        self.drained.load(Ordering::SeqCst)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -280,7 +982,9 @@ pub struct StreamInfo {
     pub index: i16,
     pub kind: Type,
     pub fps: Option<Rational>,
-    pub audio_spec: Option<AudioSpec>
+    pub audio_spec: Option<AudioSpec>,
+    // Cover art of an audio file: a single picture rather than a video
+    pub attached_pic: bool,
 }
 
 impl DecodeThread<Video> {
@@ -292,73 +996,186 @@ impl DecodeThread<Video> {
         packet_rx: Receiver<ThreadData>,
         thread_name: Option<String>,
         config: Option<ThreadConfig>,
-        scaling_config: Mutex<MDecodeOptions>
+        scaling_config: Mutex<MDecodeOptions>,
+        seek_serial: Arc<AtomicU32>,
+        output_size: Arc<RwLock<Option<(u32, u32)>>>,
+        video_filters: Arc<RwLock<Option<String>>>,
+        deinterlace: Arc<RwLock<Deinterlace>>,
+        interlaced: Arc<AtomicBool>,
+        rotation: Arc<RwLock<Option<u32>>>,
+        tonemap: Arc<RwLock<Tonemap>>
     ) -> DecodeThread<Video> {
         let config = config.unwrap_or_default();
         let (output_tx, output_rx) = mpsc::sync_channel(config.buffer_capacity.into());
-        let stream_info: StreamInfo = stream.convert();
+        let drained = Arc::new(AtomicBool::new(false));
+        let c_drained = Arc::clone(&drained);
+        let mut stream_info: StreamInfo = stream.convert();
+        // Frames go out in a finer time base than the stream's, the player reads it from here
+        let output_time_base = Deinterlace::field_time_base(stream_info.time_base);
+        stream_info.time_base = output_time_base;
         let c_stream_info = stream_info.clone();
         let parameters = stream.parameters();
+        // Phones store portrait video sideways along with the turn that brings it upright
+        let orientation = Orientation::from_stream(stream);
         let handle = thread::Builder
             ::new()
             .name(thread_name.unwrap_or("media".to_string()))
             .spawn(move || {
-                let mut video_decoder = Context::from_parameters(parameters)
-                    .unwrap()
-                    .decoder()
-                    .video()
-                    .unwrap();
-                if let Rational(0, 1) = video_decoder.time_base() {
-                    video_decoder.set_time_base(config.time_base);
-                }
-                let c_stream_info = c_stream_info;
-
                 let scaling_config_;
                 {
                     scaling_config_ = Some(scaling_config.lock().unwrap().clone());
                 }
                 let scaling_config = scaling_config_.unwrap();
 
-                let mut scaling_context = ffmpeg::software::scaling::Context::get(
-                    video_decoder.format(),
-                    video_decoder.width(),
-                    video_decoder.height(),
-                    scaling_config.pixel_format,
-                    width_from_ar(
-                        Rational(video_decoder.width() as i32, video_decoder.height() as i32),
-                        scaling_config.window_default_size.1
-                    ),
-                    scaling_config.window_default_size.1,
-                    scaling_config.scaling_flag
-                );
+                let mut video_context = Context::from_parameters(parameters).unwrap();
+                video_context.set_threading(scaling_config.threading());
+                let mut video_decoder = video_context.decoder().video().unwrap();
+                if let Rational(0, 1) = video_decoder.time_base() {
+                    video_decoder.set_time_base(config.time_base);
+                }
+                if orientation != Orientation::default() {
+                    println!("video orientation: {:?}", orientation);
+                }
+                let c_stream_info = c_stream_info;
+
+                // Rebuilt whenever the frames or the size asked for by the player change
+                let mut scaling_context = None;
+                let mut scaled_for = None;
+                // The user's filters, run before scaling and rebuilt like the scaler
+                let mut filter_graph = None;
+                let mut filtered_for = None;
                 let mut frame_buffer = Video::empty();
                 let mut counter = 0;
-                while let Ok(ThreadData::Packet(packet)) = packet_rx.recv() {
-                    while let Err(_) = video_decoder.send_packet(&packet) {
-                        if let Ok(_) = video_decoder.receive_frame(&mut frame_buffer) {
-                            if let Ok(ref mut scaler) = scaling_context {
-                                let mut output_buffer = Video::empty();
-                                if let Ok(()) = scaler.run(&frame_buffer, &mut output_buffer) {
-                                    if let Some(0) | None = output_buffer.pts() {
-                                        output_buffer.set_pts(
-                                            Some(
-                                                ((counter as f32) *
-                                                    calculate_tpf_from_time_base(
-                                                        video_decoder.time_base(),
-                                                        video_decoder
-                                                            .frame_rate()
-                                                            .unwrap_or(c_stream_info.fps.unwrap())
-                                                    )) as i64
-                                            )
-                                        );
-                                    }
-                                    counter += 1;
-                                    let _ = output_tx.send(output_buffer);
-                                }
-
+                let mut serial = seek_serial.load(Ordering::SeqCst);
+                loop {
+                    // `None` is the end of the stream
+                    let packet = match packet_rx.recv() {
+                        Ok(ThreadData::Packet(packet)) => Some(packet),
+                        Ok(ThreadData::Eof) => None,
+                        Ok(ThreadData::Flush(to)) => {
+                            video_decoder.flush();
+                            // Filters like hqdn3d carry state from frame to frame
+                            filter_graph = None;
+                            filtered_for = None;
+                            serial = to;
+                            continue;
+                        }
+                        Ok(ThreadData::Kill) | Err(_) => {
+                            break;
+                        }
+                    };
+                    if seek_serial.load(Ordering::SeqCst) != serial {
+                        continue;
+                    }
+                    match &packet {
+                        Some(packet) => {
+                            if let Err(err) = video_decoder.send_packet(packet) {
+                                println!("video decoder rejected packet: {:?}", err);
+                            }
+                        }
+                        None => {
+                            let _ = video_decoder.send_eof();
+                        }
+                    }
+                    let mut decoded = Vec::new();
+                    while let Ok(_) = video_decoder.receive_frame(&mut frame_buffer) {
+                        if frame_buffer.is_interlaced() {
+                            interlaced.store(true, Ordering::SeqCst);
+                        }
+                        let graph = video_graph(
+                            &mut filter_graph,
+                            &mut filtered_for,
+                            &frame_buffer,
+                            video_chain(
+                                &deinterlace,
+                                interlaced.load(Ordering::SeqCst),
+                                &tonemap,
+                                Colorimetry::of(&frame_buffer).is_hdr(),
+                                orientation,
+                                &rotation,
+                                &video_filters
+                            ),
+                            video_decoder.time_base()
+                        );
+                        match graph {
+                            Some(graph) => {
+                                // Filters go by the pts, which not every decoder sets
+                                frame_buffer.set_pts(
+                                    frame_buffer.timestamp().or(frame_buffer.pts())
+                                );
+                                decoded.extend(
+                                    run_video_filter(graph, &frame_buffer, output_time_base)
+                                );
+                            }
+                            None => {
+                                frame_buffer.set_pts(
+                                    frame_buffer
+                                        .timestamp()
+                                        .or(frame_buffer.pts())
+                                        .map(|pts| {
+                                            pts.rescale(video_decoder.time_base(), output_time_base)
+                                        })
+                                );
+                                decoded.push(mem::replace(&mut frame_buffer, Video::empty()));
+                            }
+                        }
+                    }
+                    if packet.is_none() && let Some(ref mut graph) = filter_graph {
+                        decoded.extend(drain_video_filter(graph, output_time_base));
+                        // A drained graph takes no more frames
+                        filter_graph = None;
+                        filtered_for = None;
+                    }
+                    for frame in decoded {
+                        // Formats the renderer takes as they are skip swscale, as long as it knows
+                        // their matrix
+                        let mut output_buffer = if
+                            scaling_config.native_formats.contains(&frame.format()) &&
+                            Colorimetry::of(&frame).sdl_colorspace().is_some()
+                        {
+                            frame
+                        } else if
+                            let Some(scaler) = video_scaler(
+                                &mut scaling_context,
+                                &mut scaled_for,
+                                &frame,
+                                &scaling_config,
+                                output_size.read().ok().and_then(|size| *size)
+                            )
+                        {
+                            let mut scaled = Video::empty();
+                            if scaler.run(&frame, &mut scaled).is_err() {
                                 continue;
                             }
-                            let _ = output_tx.send(frame_buffer.clone());
+                            // swscale only writes the pixels, the pixel aspect is picked so the
+                            // picture keeps the shape it had before scaling
+                            scaled.set_pts(frame.pts());
+                            set_sample_aspect(&mut scaled, &frame);
+                            scaled
+                        } else {
+                            frame
+                        };
+                        if let Some(0) | None = output_buffer.pts() {
+                            output_buffer.set_pts(
+                                Some(
+                                    ((counter as f32) *
+                                        calculate_tpf_from_time_base(
+                                            output_time_base,
+                                            video_decoder
+                                                .frame_rate()
+                                                .unwrap_or(c_stream_info.fps.unwrap())
+                                        )) as i64
+                                )
+                            );
+                        }
+                        counter += 1;
+                        send_output(&output_tx, output_buffer, &seek_serial, serial);
+                    }
+                    if packet.is_none() {
+                        // A drained decoder takes no more packets until it is flushed
+                        video_decoder.flush();
+                        if seek_serial.load(Ordering::SeqCst) == serial {
+                            c_drained.store(true, Ordering::SeqCst);
                         }
                     }
                 }
@@ -370,6 +1187,7 @@ impl DecodeThread<Video> {
             handle,
             output_rx,
             stream_info,
+            drained,
         }
     }
 }
@@ -379,25 +1197,132 @@ impl DecodeThread<Audio> {
         parameters: Parameters,
         packet_rx: Receiver<ThreadData>,
         thread_name: Option<String>,
-        config: Option<ThreadConfig>
+        config: Option<ThreadConfig>,
+        seek_serial: Arc<AtomicU32>,
+        playback_speed: Arc<RwLock<f64>>,
+        audio_filters: Arc<RwLock<Option<String>>>,
+        threading: threading::Config,
+        output: Option<AudioSpec>
     ) -> DecodeThread<Audio> {
         let config = config.unwrap_or_default();
         let (output_tx, output_rx) = mpsc::sync_channel(config.buffer_capacity.into());
+        let drained = Arc::new(AtomicBool::new(false));
+        let c_drained = Arc::clone(&drained);
         let stream_info = parameters.convert();
         let handle = thread::Builder
             ::new()
             .name(thread_name.unwrap_or("media".to_string()))
             .spawn(move || {
-                let mut audio_decoder = Context::from_parameters(parameters)
-                    .unwrap()
-                    .decoder()
-                    .audio()
-                    .unwrap();
+                let mut audio_context = Context::from_parameters(parameters).unwrap();
+                audio_context.set_threading(threading);
+                let mut audio_decoder = audio_context.decoder().audio().unwrap();
                 let mut frame_buffer = Audio::empty();
-                while let Ok(ThreadData::Packet(packet)) = packet_rx.recv() {
-                    audio_decoder.send_packet(&packet).unwrap();
-                    if let Ok(_) = audio_decoder.receive_frame(&mut frame_buffer) {
-                        output_tx.send(frame_buffer.clone()).unwrap();
+                let mut serial = seek_serial.load(Ordering::SeqCst);
+                // Effects and time stretching, the graph is rebuilt whenever either changes
+                let mut filter_graph: Option<filter::Graph> = None;
+                let mut tempo_speed: Option<f64> = None;
+                let mut graph_filters: Option<String> = None;
+                // Stretched frames are stamped with media time rather than output time, so the
+                // player's clock stays on the file's timeline.
+                let mut next_pts: Option<f64> = None;
+                // Last stage, into the spec the player opened the device with
+                let mut resampler = output.map(|spec| {
+                    AudioResampler::new(
+                        spec.format.unwrap_or(AudioFormat::F32LE).convert(),
+                        spec.channels.unwrap_or(2),
+                        spec.freq.unwrap_or(48000) as u32
+                    )
+                });
+                loop {
+                    // `None` is the end of the stream
+                    let packet = match packet_rx.recv() {
+                        Ok(ThreadData::Packet(packet)) => Some(packet),
+                        Ok(ThreadData::Eof) => None,
+                        Ok(ThreadData::Flush(to)) => {
+                            audio_decoder.flush();
+                            serial = to;
+                            tempo_speed = None;
+                            if let Some(ref mut resampler) = resampler {
+                                resampler.reset();
+                            }
+                            continue;
+                        }
+                        Ok(ThreadData::Kill) | Err(_) => {
+                            break;
+                        }
+                    };
+                    if seek_serial.load(Ordering::SeqCst) != serial {
+                        continue;
+                    }
+                    match &packet {
+                        Some(packet) => {
+                            if let Err(err) = audio_decoder.send_packet(packet) {
+                                println!("audio decoder rejected packet: {:?}", err);
+                            }
+                        }
+                        None => {
+                            let _ = audio_decoder.send_eof();
+                        }
+                    }
+                    while let Ok(_) = audio_decoder.receive_frame(&mut frame_buffer) {
+                        let speed = playback_speed.read().map(|speed| *speed).unwrap_or(1.0);
+                        let filters_changed = audio_filters
+                            .read()
+                            .map_or(false, |filters| *filters != graph_filters);
+                        if tempo_speed != Some(speed) || filters_changed {
+                            if let Ok(filters) = audio_filters.read() {
+                                graph_filters = filters.clone();
+                            }
+                            filter_graph = effects_graph(
+                                graph_filters.as_deref(),
+                                speed,
+                                &frame_buffer,
+                                config.time_base
+                            );
+                            tempo_speed = Some(speed);
+                            next_pts = None;
+                        }
+                        let Some(ref mut graph) = filter_graph else {
+                            send_audio(
+                                &output_tx,
+                                &mut resampler,
+                                frame_buffer.clone(),
+                                &seek_serial,
+                                serial
+                            );
+                            continue;
+                        };
+                        if let None = next_pts {
+                            next_pts = frame_buffer.pts().map(|pts| pts as f64);
+                        }
+                        for mut stretched in run_audio_filter(graph, &frame_buffer) {
+                            stamp_media_pts(&mut stretched, &mut next_pts, speed, config.time_base);
+                            send_audio(&output_tx, &mut resampler, stretched, &seek_serial, serial);
+                        }
+                    }
+                    if packet.is_none() {
+                        // atempo and the effects hold on to a few samples of their own
+                        if let Some(ref mut graph) = filter_graph {
+                            let speed = tempo_speed.unwrap_or(1.0);
+                            for mut stretched in drain_audio_filter(graph) {
+                                stamp_media_pts(&mut stretched, &mut next_pts, speed, config.time_base);
+                                send_audio(&output_tx, &mut resampler, stretched, &seek_serial, serial);
+                            }
+                        }
+                        // Then the resampler, its tail goes right after the last frame
+                        if
+                            let Some(ref mut resampler) = resampler &&
+                            let Some(tail) = resampler.flush()
+                        {
+                            send_output(&output_tx, tail, &seek_serial, serial);
+                        }
+                        // Neither the decoder nor the graph take input after their EOF
+                        audio_decoder.flush();
+                        filter_graph = None;
+                        tempo_speed = None;
+                        if seek_serial.load(Ordering::SeqCst) == serial {
+                            c_drained.store(true, Ordering::SeqCst);
+                        }
                     }
                 }
             })
@@ -406,8 +1331,199 @@ impl DecodeThread<Audio> {
             handle,
             output_rx,
             stream_info,
+            drained,
+        }
+    }
+}
+
+impl DecodeThread<SubtitleCue> {
+    pub fn spawn(
+        stream: &Stream<'_>,
+        packet_rx: Receiver<ThreadData>,
+        thread_name: Option<String>,
+        config: Option<ThreadConfig>,
+        reference_size: (u32, u32),
+        seek_serial: Arc<AtomicU32>
+    ) -> DecodeThread<SubtitleCue> {
+        let config = config.unwrap_or_default();
+        let (output_tx, output_rx) = mpsc::sync_channel(config.buffer_capacity.into());
+        let stream_info: StreamInfo = stream.convert();
+        let parameters = stream.parameters();
+        let handle = thread::Builder
+            ::new()
+            .name(thread_name.unwrap_or("media".to_string()))
+            .spawn(move || {
+                let mut subtitle_decoder = match
+                    Context::from_parameters(parameters).and_then(|context| {
+                        context.decoder().subtitle()
+                    })
+                {
+                    Ok(decoder) => decoder,
+                    Err(err) => {
+                        println!("unable to open subtitle decoder: {:?}", err);
+                        return;
+                    }
+                };
+                let mut serial = seek_serial.load(Ordering::SeqCst);
+                loop {
+                    let packet = match packet_rx.recv() {
+                        Ok(ThreadData::Packet(packet)) => packet,
+                        // Subtitle decoders do not hold anything back
+                        Ok(ThreadData::Eof) => {
+                            continue;
+                        }
+                        Ok(ThreadData::Flush(to)) => {
+                            subtitle_decoder.flush();
+                            serial = to;
+                            continue;
+                        }
+                        Ok(ThreadData::Kill) | Err(_) => {
+                            break;
+                        }
+                    };
+                    if seek_serial.load(Ordering::SeqCst) != serial {
+                        continue;
+                    }
+                    let mut subtitle = ffmpeg::Subtitle::new();
+                    match subtitle_decoder.decode(&packet, &mut subtitle) {
+                        Ok(true) => {
+                            let pts = packet
+                                .pts()
+                                .map(|pts| (pts as f64) * f64::from(config.time_base));
+                            if let Some(mut cue) = SubtitleCue::from_subtitle(
+                                &subtitle,
+                                pts,
+                                reference_size
+                            ) {
+                                // Text formats carry their duration on the packet
+                                if cue.end.is_none() && !cue.items.is_empty() && packet.duration() > 0 {
+                                    cue.end = Some(
+                                        cue.start +
+                                            (packet.duration() as f64) * f64::from(config.time_base)
+                                    );
+                                }
+                                send_output(&output_tx, cue, &seek_serial, serial);
+                            }
+                        }
+                        Ok(false) => {}
+                        Err(err) => println!("subtitle decoder rejected packet: {:?}", err),
+                    }
+                }
+            })
+            .expect("Unable to spawn subtitle thread.");
+        DecodeThread {
+            handle,
+            output_rx,
+            stream_info,
+            drained: Arc::new(AtomicBool::new(false)),
         }
     }
 }
 
-impl MediaThread {}
+impl MediaThread {
+    pub fn play(&self) {
+        let _ = self.command_tx.send(MediaThreadCommand::Play);
+    }
+
+    pub fn pause(&self) {
+        let _ = self.command_tx.send(MediaThreadCommand::Pause);
+    }
+
+    pub fn seek(&self, to: i64) {
+        // Bumped here rather than in the media thread so the decoders start dropping stale
+        // packets right away, the media thread may be stuck sending into a full channel.
+        self.seek_serial.fetch_add(1, Ordering::SeqCst);
+        // Whatever was drained belongs to the old position
+        if let Ok(mut report) = self.status.write() {
+            report.eof = false;
+        }
+        let _ = self.command_tx.send(MediaThreadCommand::Seek(to));
+    }
+
+    /// Picked up by the audio decoder on its next frame, frames already decoded keep their tempo.
+    pub fn set_speed(&self, speed: f64) {
+        if let Ok(mut playback_speed) = self.playback_speed.write() {
+            *playback_speed = speed;
+        }
+    }
+
+    /// Like the speed, picked up by the audio decoder on its next frame.
+    pub fn set_audio_filters(&self, filters: Option<String>) {
+        if let Ok(mut audio_filters) = self.audio_filters.write() {
+            *audio_filters = filters;
+        }
+    }
+
+    /// Frames decoded from here on go through `filters`, the ones already buffered do not.
+    pub fn set_video_filters(&self, filters: Option<String>) {
+        if let Ok(mut video_filters) = self.video_filters.write() {
+            *video_filters = filters;
+        }
+    }
+
+    /// Like the filters, applies to the frames decoded from here on.
+    pub fn set_deinterlace(&self, deinterlace: Deinterlace) {
+        if let Ok(mut current) = self.deinterlace.write() {
+            *current = deinterlace;
+        }
+    }
+
+    /// Clockwise degrees to turn the frames decoded from here on, `None` goes back to the
+    /// stream's own orientation.
+    pub fn set_rotation(&self, rotation: Option<u32>) {
+        if let Ok(mut current) = self.rotation.write() {
+            *current = rotation;
+        }
+    }
+
+    /// How PQ/HLG frames decoded from here on are brought down to SDR.
+    pub fn set_tonemap(&self, tonemap: Tonemap) {
+        if let Ok(mut current) = self.tonemap.write() {
+            *current = tonemap;
+        }
+    }
+
+    /// Whether the video has shown interlaced frames so far, what auto deinterlacing goes by.
+    pub fn interlaced(&self) -> bool {
+        self.interlaced.load(Ordering::SeqCst)
+    }
+
+    pub fn report(&self) -> Option<MediaThreadReport> {
+        self.status
+            .read()
+            .ok()
+            .map(|report| report.clone())
+    }
+
+    /// The switch over to a preloaded file happened, the decoders are the player's current ones.
+    pub fn promote(&self) {
+        self.preload.store(false, Ordering::SeqCst);
+    }
+
+    /// Switches to another audio stream, the player seeks to its position right after so the new
+    /// decoder starts from what is being played rather than from where the demuxer got to.
+    pub fn select_audio(&self, selection: StreamSelection) {
+        let _ = self.command_tx.send(MediaThreadCommand::SelectAudio(selection));
+    }
+
+    /// Same as `select_audio`, `None` turns subtitles off.
+    pub fn select_subtitle(&self, selection: Option<StreamSelection>) {
+        let _ = self.command_tx.send(MediaThreadCommand::SelectSubtitle(selection));
+    }
+
+    /// Frames decoded from here on are scaled to `width` x `height`, the ones already buffered
+    /// keep their size.
+    pub fn resize(&self, width: u32, height: u32) {
+        if let Ok(mut output_size) = self.output_size.write() {
+            *output_size = Some((width, height));
+        }
+    }
+
+    /// The media thread sends `ThreadData::Kill` down to its decoders on the way out. Bumping the
+    /// serial gets them to skip what is still queued, so a demuxer blocked on a full packet
+    /// channel gets to the command quickly.
+    pub fn exit(self) {
+        self.seek_serial.fetch_add(1, Ordering::SeqCst);
+        let _ = self.command_tx.send(MediaThreadCommand::Exit);
+    }
+}
@@ -0,0 +1,493 @@
+use std::mem;
+
+use ffmpeg_next::{
+    self as ffmpeg,
+    ChannelLayout,
+    Dictionary,
+    Rational,
+    Rescale,
+    Stream,
+    codec::packet::side_data,
+    filter,
+    format::Sample,
+    frame::{ Audio, Video },
+    software::resampling,
+};
+
+/// Builds an audio filter graph running `spec` on frames shaped like `frame`. The sink keeps the
+/// frame's format, rate and layout so nothing downstream has to change.
+pub fn audio_filter(
+    spec: &str,
+    frame: &Audio,
+    time_base: Rational
+) -> Result<filter::Graph, ffmpeg::Error> {
+    let mut graph = filter::Graph::new();
+    let layout = frame_layout(frame);
+
+    let args = format!(
+        "time_base={}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+        time_base,
+        frame.rate(),
+        frame.format().name(),
+        layout.bits()
+    );
+    graph.add(&filter::find("abuffer").ok_or(ffmpeg::Error::FilterNotFound)?, "in", &args)?;
+    graph.add(&filter::find("abuffersink").ok_or(ffmpeg::Error::FilterNotFound)?, "out", "")?;
+    {
+        let mut out = graph.get("out").ok_or(ffmpeg::Error::FilterNotFound)?;
+        out.set_sample_format(frame.format());
+        out.set_channel_layout(layout);
+        out.set_sample_rate(frame.rate());
+    }
+
+    graph.output("in", 0)?.input("out", 0)?.parse(spec)?;
+    graph.validate()?;
+    Ok(graph)
+}
+
+/// Runs `frame` through `graph` and returns everything the sink has ready.
+pub fn run_audio_filter(graph: &mut filter::Graph, frame: &Audio) -> Vec<Audio> {
+    let mut output = Vec::new();
+    if let Some(mut input) = graph.get("in") {
+        if let Err(err) = input.source().add(frame) {
+            println!("audio filter rejected frame: {:?}", err);
+            return output;
+        }
+    }
+    if let Some(mut sink) = graph.get("out") {
+        let mut filtered = Audio::empty();
+        while let Ok(()) = sink.sink().frame(&mut filtered) {
+            output.push(filtered.clone());
+        }
+    }
+    output
+}
+
+/// Signals the end of the stream to `graph` and returns what it was still holding on to. The
+/// graph takes no more input afterwards.
+pub fn drain_audio_filter(graph: &mut filter::Graph) -> Vec<Audio> {
+    let mut output = Vec::new();
+    if let Some(mut input) = graph.get("in") {
+        if let Err(err) = input.source().flush() {
+            println!("unable to drain audio filter: {:?}", err);
+            return output;
+        }
+    }
+    if let Some(mut sink) = graph.get("out") {
+        let mut filtered = Audio::empty();
+        while let Ok(()) = sink.sink().frame(&mut filtered) {
+            output.push(filtered.clone());
+        }
+    }
+    output
+}
+
+/// The `filters` chain followed by atempo for `speed`, `None` when there is nothing to run. A chain
+/// that does not parse is reported and left out, so the tempo change still goes through.
+pub fn effects_graph(
+    filters: Option<&str>,
+    speed: f64,
+    frame: &Audio,
+    time_base: Rational
+) -> Option<filter::Graph> {
+    let tempo = (speed != 1.0).then(|| atempo_chain(speed));
+    if let Some(filters) = filters {
+        let chain = match &tempo {
+            Some(tempo) => format!("{},{}", filters, tempo),
+            None => filters.to_string(),
+        };
+        match audio_filter(&chain, frame, time_base) {
+            Ok(graph) => {
+                return Some(graph);
+            }
+            Err(err) => println!("unable to set up audio filters \"{}\": {:?}", filters, err),
+        }
+    }
+    audio_filter(&tempo?, frame, time_base)
+        .inspect_err(|err| println!("unable to set up atempo: {:?}", err))
+        .ok()
+}
+
+/// atempo only takes factors in [0.5, 2.0] on older FFmpeg builds, so anything outside of that is
+/// split into a chain of instances.
+pub fn atempo_chain(speed: f64) -> String {
+    let mut speed = speed;
+    let mut chain = Vec::new();
+    while speed < 0.5 {
+        chain.push("atempo=0.5".to_string());
+        speed /= 0.5;
+    }
+    while speed > 2.0 {
+        chain.push("atempo=2.0".to_string());
+        speed /= 2.0;
+    }
+    chain.push(format!("atempo={}", speed));
+    chain.join(",")
+}
+
+// Decoders leave the layout empty when the stream does not carry one
+fn frame_layout(frame: &Audio) -> ChannelLayout {
+    if frame.channel_layout().is_empty() {
+        ChannelLayout::default(frame.channels() as i32)
+    } else {
+        frame.channel_layout()
+    }
+}
+
+/// Converts frames of any sample format, channel layout and rate to a single output spec. Extra
+/// channels are downmixed with swresample's matrix (center and surrounds at -3 dB), scaled down
+/// where needed so a full scale 5.1/7.1 mix does not clip.
+pub struct AudioResampler {
+    format: Sample,
+    layout: ChannelLayout,
+    rate: u32,
+    context: Option<resampling::Context>,
+    // Format, layout and rate of the frames `context` was built for
+    built_for: Option<(Sample, ChannelLayout, u32)>,
+}
+
+impl AudioResampler {
+    pub fn new(format: Sample, channels: i32, rate: u32) -> AudioResampler {
+        AudioResampler {
+            format,
+            layout: ChannelLayout::default(channels),
+            rate,
+            context: None,
+            built_for: None,
+        }
+    }
+
+    /// Converts `frame`, keeping its pts. `None` while swresample is still buffering (or could
+    /// not be set up for the frame).
+    pub fn run(&mut self, frame: &Audio) -> Option<Audio> {
+        let input = (frame.format(), frame_layout(frame), frame.rate());
+        if self.built_for != Some(input) {
+            let mut options = Dictionary::new();
+            options.set("rematrix_maxval", "1.0");
+            self.context = resampling::Context
+                ::get_with(input.0, input.1, input.2, self.format, self.layout, self.rate, options)
+                .inspect_err(|err| println!("unable to set up resampler: {:?}", err))
+                .ok();
+            self.built_for = Some(input);
+        }
+        let capacity =
+            ((frame.samples() as u64) * (self.rate as u64)) / (frame.rate().max(1) as u64);
+        let context = self.context.as_mut()?;
+        let mut output = Audio::new(self.format, Self::capacity(context, capacity), self.layout);
+        context.run(frame, &mut output).ok()?;
+        output.set_pts(frame.pts());
+        (output.samples() > 0).then_some(output)
+    }
+
+    /// What swresample still holds at the end of the stream.
+    pub fn flush(&mut self) -> Option<Audio> {
+        let context = self.context.as_mut()?;
+        let mut output = Audio::new(self.format, Self::capacity(context, 0), self.layout);
+        context.flush(&mut output).ok()?;
+        (output.samples() > 0).then_some(output)
+    }
+
+    /// Drops the buffered samples, they belong to the position before a seek.
+    pub fn reset(&mut self) {
+        self.context = None;
+        self.built_for = None;
+    }
+
+    // Room for `samples` plus whatever swresample has been holding back
+    fn capacity(context: &resampling::Context, samples: u64) -> usize {
+        let delay = context.delay().map_or(0, |delay| delay.output.max(0) as u64);
+        (samples + delay + 256) as usize
+    }
+}
+
+// Center frequencies of the graphic EQ bands in Hz, an octave apart
+pub const EQ_BANDS: [u32; 10] = [31, 62, 125, 250, 500, 1000, 2000, 4000, 8000, 16000];
+
+/// Equalizer and tone settings, run by the audio decoder as the chain from `filter_chain`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AudioEffects {
+    // Gain of each `EQ_BANDS` band in dB
+    pub eq: [f32; 10],
+    // Shelving gains in dB
+    pub bass: f32,
+    pub treble: f32,
+    // Filters in libavfilter syntax, run after the rest as they are
+    pub custom: Option<String>,
+}
+
+impl AudioEffects {
+    /// `None` when everything is flat and there is nothing to run.
+    pub fn filter_chain(&self) -> Option<String> {
+        let mut filters: Vec<String> = EQ_BANDS.iter()
+            .zip(self.eq)
+            .filter(|(_, gain)| *gain != 0.0)
+            .map(|(frequency, gain)| format!("equalizer=f={}:t=o:w=1:g={}", frequency, gain))
+            .collect();
+        if self.bass != 0.0 {
+            filters.push(format!("bass=g={}", self.bass));
+        }
+        if self.treble != 0.0 {
+            filters.push(format!("treble=g={}", self.treble));
+        }
+        if let Some(custom) = &self.custom {
+            filters.push(custom.clone());
+        }
+        (!filters.is_empty()).then(|| filters.join(","))
+    }
+}
+
+/// Builds a video filter graph running `spec` on frames shaped like `frame`. Unlike the audio
+/// graphs the sink takes whatever the filters put out, the scaler deals with it.
+pub fn video_filter(
+    spec: &str,
+    frame: &Video,
+    time_base: Rational
+) -> Result<filter::Graph, ffmpeg::Error> {
+    let mut graph = filter::Graph::new();
+    let sample_aspect = match frame.aspect_ratio() {
+        Rational(num, den) if num > 0 && den > 0 => Rational(num, den),
+        _ => Rational(1, 1),
+    };
+
+    let args = format!(
+        "video_size={}x{}:pix_fmt={}:time_base={}:pixel_aspect={}",
+        frame.width(),
+        frame.height(),
+        ffmpeg::ffi::AVPixelFormat::from(frame.format()) as i32,
+        time_base,
+        sample_aspect
+    );
+    graph.add(&filter::find("buffer").ok_or(ffmpeg::Error::FilterNotFound)?, "in", &args)?;
+    graph.add(&filter::find("buffersink").ok_or(ffmpeg::Error::FilterNotFound)?, "out", "")?;
+
+    graph.output("in", 0)?.input("out", 0)?.parse(spec)?;
+    graph.validate()?;
+    Ok(graph)
+}
+
+/// Runs `frame` through `graph` and returns everything the sink has ready, with the pts in
+/// `time_base`.
+pub fn run_video_filter(
+    graph: &mut filter::Graph,
+    frame: &Video,
+    time_base: Rational
+) -> Vec<Video> {
+    if let Some(mut input) = graph.get("in") {
+        if let Err(err) = input.source().add(frame) {
+            println!("video filter rejected frame: {:?}", err);
+            return Vec::new();
+        }
+    }
+    video_sink_frames(graph, time_base)
+}
+
+/// Same as `drain_audio_filter`, for video graphs.
+pub fn drain_video_filter(graph: &mut filter::Graph, time_base: Rational) -> Vec<Video> {
+    if let Some(mut input) = graph.get("in") {
+        if let Err(err) = input.source().flush() {
+            println!("unable to drain video filter: {:?}", err);
+            return Vec::new();
+        }
+    }
+    video_sink_frames(graph, time_base)
+}
+
+// Takes what the sink has ready. Filters that double the frame rate halve the time base on the
+// way, the pts go to `time_base`, which has to be fine enough for the extra frames (see
+// `Deinterlace::field_time_base`).
+fn video_sink_frames(graph: &mut filter::Graph, time_base: Rational) -> Vec<Video> {
+    let mut output = Vec::new();
+    if let Some(mut sink) = graph.get("out") {
+        let sink_time_base = sink.sink().time_base();
+        let mut filtered = Video::empty();
+        while let Ok(()) = sink.sink().frame(&mut filtered) {
+            if sink_time_base != time_base {
+                filtered.set_pts(filtered.pts().map(|pts| pts.rescale(sink_time_base, time_base)));
+            }
+            output.push(mem::replace(&mut filtered, Video::empty()));
+        }
+    }
+    output
+}
+
+/// Picture adjustments for the video decoder, run as the chain from `filter_chain` before
+/// scaling.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VideoEffects {
+    // Width, height, x and y of the area to keep, in source pixels
+    pub crop: Option<(u32, u32, u32, u32)>,
+    // Clockwise in degrees: 0, 90, 180 or 270
+    pub rotation: u32,
+    pub hflip: bool,
+    pub vflip: bool,
+    // hqdn3d luma strength, 0 for none
+    pub denoise: f32,
+    // unsharp luma amount, negative blurs, 0 for none
+    pub sharpen: f32,
+    // Filters in libavfilter syntax, run after the rest as they are
+    pub custom: Option<String>,
+}
+
+impl VideoEffects {
+    /// `None` when there is nothing to do to the picture.
+    pub fn filter_chain(&self) -> Option<String> {
+        let mut filters = Vec::new();
+        if let Some((width, height, x, y)) = self.crop {
+            filters.push(format!("crop={}:{}:{}:{}", width, height, x, y));
+        }
+        if self.denoise != 0.0 {
+            filters.push(format!("hqdn3d={}", self.denoise));
+        }
+        if self.sharpen != 0.0 {
+            filters.push(format!("unsharp=5:5:{}", self.sharpen));
+        }
+        let orientation = Orientation {
+            rotation: self.rotation,
+            hflip: self.hflip,
+            vflip: self.vflip,
+        };
+        if let Some(filter) = orientation.filter() {
+            filters.push(filter);
+        }
+        if let Some(custom) = &self.custom {
+            filters.push(custom.clone());
+        }
+        (!filters.is_empty()).then(|| filters.join(","))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeinterlaceMode {
+    Off,
+    // Every frame, whatever its flags say
+    On,
+    // Once the decoder flags a frame as interlaced, and then only the flagged frames
+    Auto,
+}
+
+/// How the video decoder deinterlaces, with bwdif ahead of the user's filters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deinterlace {
+    pub mode: DeinterlaceMode,
+    // A frame per field, twice the frame rate, instead of one per frame
+    pub double_rate: bool,
+}
+
+impl Deinterlace {
+    /// The filter for a stream that has or has not (`interlaced`) shown an interlaced frame so
+    /// far, `None` when it needs none. The field order comes from each frame's flags.
+    pub fn filter(&self, interlaced: bool) -> Option<String> {
+        let deint = match self.mode {
+            DeinterlaceMode::On => "all",
+            DeinterlaceMode::Auto if interlaced => "interlaced",
+            DeinterlaceMode::Auto | DeinterlaceMode::Off => {
+                return None;
+            }
+        };
+        let mode = if self.double_rate { "send_field" } else { "send_frame" };
+        Some(format!("bwdif=mode={}:parity=auto:deint={}", mode, deint))
+    }
+
+    /// The time base video frames leave the decode thread in, twice as fine as the stream's. At
+    /// field rate the second field of a frame sits halfway to the next one, which a stream
+    /// counting whole frames has no pts for.
+    pub fn field_time_base(time_base: Rational) -> Rational {
+        match time_base.1.checked_mul(2) {
+            Some(den) => Rational(time_base.0, den),
+            None => time_base,
+        }
+    }
+
+    /// For the stats line, e.g. "auto (interlaced, field rate)".
+    pub fn state(&self, interlaced: bool) -> String {
+        let mode = match self.mode {
+            DeinterlaceMode::Off => {
+                return "off".to_string();
+            }
+            DeinterlaceMode::On => "on",
+            DeinterlaceMode::Auto => "auto",
+        };
+        let source = if interlaced { "interlaced" } else { "progressive" };
+        let rate = if self.double_rate { "field rate" } else { "frame rate" };
+        format!("{} ({}, {})", mode, source, rate)
+    }
+}
+
+/// Turns and flips that bring the picture upright, the flips go after the rotation.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Orientation {
+    // Clockwise in degrees: 0, 90, 180 or 270
+    pub rotation: u32,
+    pub hflip: bool,
+    pub vflip: bool,
+}
+
+impl Orientation {
+    /// What the stream's display matrix asks for, or its older `rotate` tag when it has none.
+    /// Like FFmpeg's autorotate, angles other than quarter turns are left alone.
+    pub fn from_stream(stream: &Stream) -> Orientation {
+        let matrix = stream
+            .side_data()
+            .find(|entry| entry.kind() == side_data::Type::DisplayMatrix)
+            .and_then(|entry| {
+                let data = entry.data();
+                (data.len() >= 36).then(|| {
+                    let mut matrix = [0i32; 9];
+                    for (value, bytes) in matrix.iter_mut().zip(data.chunks_exact(4)) {
+                        *value = i32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    }
+                    matrix
+                })
+            });
+        if let Some(matrix) = matrix {
+            return Orientation::from_display_matrix(&matrix);
+        }
+        let rotation = stream
+            .metadata()
+            .get("rotate")
+            .and_then(|rotate| rotate.trim().parse::<i64>().ok())
+            .map(|degrees| degrees.rem_euclid(360) as u32)
+            .filter(|degrees| degrees % 90 == 0)
+            .unwrap_or(0);
+        Orientation { rotation, ..Orientation::default() }
+    }
+
+    // The same reading of the matrix as the ffmpeg command line tool
+    fn from_display_matrix(matrix: &[i32; 9]) -> Orientation {
+        // The matrix turns counterclockwise
+        let theta = -unsafe { ffmpeg::ffi::av_display_rotation_get(matrix.as_ptr()) }.round();
+        let theta = theta - 360.0 * (theta / 360.0 + 0.9 / 360.0).floor();
+        let mut orientation = Orientation::default();
+        if (theta - 90.0).abs() < 1.0 {
+            orientation.rotation = 90;
+            orientation.hflip = matrix[3] > 0;
+        } else if (theta - 180.0).abs() < 1.0 {
+            orientation.hflip = matrix[0] < 0;
+            orientation.vflip = matrix[4] < 0;
+        } else if (theta - 270.0).abs() < 1.0 {
+            orientation.rotation = 270;
+            orientation.hflip = matrix[3] < 0;
+        } else if theta.abs() < 1.0 {
+            orientation.vflip = matrix[4] < 0;
+        }
+        orientation
+    }
+
+    /// `None` when the picture is upright already.
+    pub fn filter(&self) -> Option<String> {
+        let mut filters = Vec::new();
+        match self.rotation % 360 {
+            90 => filters.push("transpose=clock"),
+            180 => filters.push("hflip,vflip"),
+            270 => filters.push("transpose=cclock"),
+            _ => {}
+        }
+        if self.hflip {
+            filters.push("hflip");
+        }
+        if self.vflip {
+            filters.push("vflip");
+        }
+        (!filters.is_empty()).then(|| filters.join(","))
+    }
+}
@@ -6,14 +6,29 @@ use std::{
 
 use ffmpeg_next::Rational;
 
-use crate::{mplayer::MPlayer, utils::clear_screen};
+use crate::{
+    audio::ReplayGainMode,
+    color::Tonemap,
+    filters::DeinterlaceMode,
+    mplayer::{EndAction, MPlayer},
+    playlist::RepeatMode,
+    utils::{StreamSelection, clear_screen},
+    view::ViewMode,
+};
 
 mod audio;
+mod color;
 mod constants;
 mod convert;
 mod core;
+mod filters;
 mod mplayer;
+mod osd;
+mod playlist;
+mod subtitle_files;
+mod subtitles;
 mod utils;
+mod view;
 
 fn main() {
     let (tx, rx) = mpsc::channel::<Command>();
@@ -28,6 +43,25 @@ fn main() {
                         let _ = tx.send(Command::Shutdown);
                         println!("shutting down mplayer");
                     }
+                    _ if line.starts_with("add ") => {
+                        let path = line["add ".len()..].replace("\"", "").replace("'", "");
+                        let _ = tx.send(Command::Enqueue(String::from(path.trim())));
+                    }
+                    _ if line.starts_with("sub-file ") => {
+                        let path = line["sub-file ".len()..].replace("\"", "").replace("'", "");
+                        let _ = tx.send(Command::SubtitleFile(String::from(path.trim())));
+                    }
+                    // Before "open", a filter string could contain it
+                    _ if line.starts_with("af ") => {
+                        let filters = line["af ".len()..].trim();
+                        let filters = (filters != "off").then(|| filters.to_string());
+                        let _ = tx.send(Command::AudioFilter(filters));
+                    }
+                    _ if line.starts_with("vf ") => {
+                        let filters = line["vf ".len()..].trim();
+                        let filters = (filters != "off").then(|| filters.to_string());
+                        let _ = tx.send(Command::VideoFilter(filters));
+                    }
                     _ if line.contains("open") => {
                         if let Some(dir) = line.split("open").nth(1) {
                             let _ = tx.send(Command::Play(String::from(
@@ -38,6 +72,297 @@ fn main() {
                     "clr" => {
                         clear_screen();
                     }
+                    "pause" => {
+                        let _ = tx.send(Command::Pause);
+                    }
+                    "resume" => {
+                        let _ = tx.send(Command::Resume);
+                    }
+                    "next" => {
+                        let _ = tx.send(Command::Next);
+                    }
+                    "prev" => {
+                        let _ = tx.send(Command::Previous);
+                    }
+                    "playlist" => {
+                        let _ = tx.send(Command::Playlist);
+                    }
+                    _ if line.starts_with("remove") => {
+                        if let Some(Ok(index)) = line.split_whitespace().nth(1).map(str::parse) {
+                            let _ = tx.send(Command::Remove(index));
+                        }
+                    }
+                    _ if line.starts_with("move") => {
+                        let mut args = line.split_whitespace().skip(1).map(str::parse);
+                        if let (Some(Ok(from)), Some(Ok(to))) = (args.next(), args.next()) {
+                            let _ = tx.send(Command::Move(from, to));
+                        }
+                    }
+                    _ if line.starts_with("repeat") => {
+                        match line.split_whitespace().nth(1) {
+                            Some("off") => {
+                                let _ = tx.send(Command::Repeat(RepeatMode::Off));
+                            }
+                            Some("one") => {
+                                let _ = tx.send(Command::Repeat(RepeatMode::One));
+                            }
+                            Some("all") => {
+                                let _ = tx.send(Command::Repeat(RepeatMode::All));
+                            }
+                            _ => {}
+                        }
+                    }
+                    "tracks" => {
+                        let _ = tx.send(Command::Tracks);
+                    }
+                    _ if line.starts_with("audio-track") => {
+                        if let Some(Ok(selection)) = line.split_whitespace().nth(1).map(str::parse) {
+                            let _ = tx.send(Command::AudioTrack(selection));
+                        }
+                    }
+                    _ if line.starts_with("video-track") => {
+                        if let Some(Ok(selection)) = line.split_whitespace().nth(1).map(str::parse) {
+                            let _ = tx.send(Command::VideoTrack(selection));
+                        }
+                    }
+                    _ if line.starts_with("sub-delay") => {
+                        if let Some(Ok(secs)) = line.split_whitespace().nth(1).map(str::parse) {
+                            let _ = tx.send(Command::SubtitleDelay(secs));
+                        }
+                    }
+                    _ if line.starts_with("sub-track") => {
+                        let selection = match line.split_whitespace().nth(1) {
+                            Some("off") => Some(None),
+                            Some(arg) => arg.parse().ok().map(Some),
+                            None => None,
+                        };
+                        if let Some(selection) = selection {
+                            let _ = tx.send(Command::SubtitleTrack(selection));
+                        }
+                    }
+                    _ if line.starts_with("view") => {
+                        match line.split_whitespace().nth(1) {
+                            Some("fit") => {
+                                let _ = tx.send(Command::View(ViewMode::Fit));
+                            }
+                            Some("fill") => {
+                                let _ = tx.send(Command::View(ViewMode::Fill));
+                            }
+                            Some("stretch") => {
+                                let _ = tx.send(Command::View(ViewMode::Stretch));
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ if line.starts_with("zoom") => {
+                        let zoom = line.split_whitespace().nth(1).and_then(|arg| arg.parse().ok());
+                        if let Some(zoom) = zoom.filter(|zoom: &f32| zoom.is_finite()) {
+                            let _ = tx.send(Command::Zoom(zoom));
+                        }
+                    }
+                    _ if line.starts_with("pan ") => {
+                        let mut args = line.split_whitespace().skip(1).map(str::parse);
+                        if
+                            let (Some(Ok(x)), Some(Ok(y))) = (args.next(), args.next()) &&
+                            f32::is_finite(x) &&
+                            f32::is_finite(y)
+                        {
+                            let _ = tx.send(Command::Pan(x, y));
+                        }
+                    }
+                    _ if line.starts_with("volume") => {
+                        let db = line.split_whitespace().nth(1).and_then(|arg| arg.parse().ok());
+                        if let Some(db) = db.filter(|db: &f32| db.is_finite()) {
+                            let _ = tx.send(Command::Volume(db));
+                        }
+                    }
+                    "mute on" => {
+                        let _ = tx.send(Command::Mute(true));
+                    }
+                    "mute off" => {
+                        let _ = tx.send(Command::Mute(false));
+                    }
+                    "eq reset" => {
+                        let _ = tx.send(Command::EqReset);
+                    }
+                    _ if line.starts_with("eq ") => {
+                        let mut args = line.split_whitespace().skip(1);
+                        let band = args.next().map(str::parse);
+                        let gain = args.next().map(str::parse);
+                        if let (Some(Ok(band)), Some(Ok(gain))) = (band, gain) {
+                            let _ = tx.send(Command::Eq(band, gain));
+                        }
+                    }
+                    _ if line.starts_with("bass") => {
+                        if let Some(Ok(db)) = line.split_whitespace().nth(1).map(str::parse) {
+                            let _ = tx.send(Command::Bass(db));
+                        }
+                    }
+                    _ if line.starts_with("treble") => {
+                        if let Some(Ok(db)) = line.split_whitespace().nth(1).map(str::parse) {
+                            let _ = tx.send(Command::Treble(db));
+                        }
+                    }
+                    "crop off" => {
+                        let _ = tx.send(Command::Crop(None));
+                    }
+                    _ if line.starts_with("crop ") => {
+                        let args: Vec<Result<u32, _>> = line
+                            .split_whitespace()
+                            .skip(1)
+                            .map(str::parse)
+                            .collect();
+                        if let [Ok(width), Ok(height), Ok(x), Ok(y)] = args[..] {
+                            let _ = tx.send(Command::Crop(Some((width, height, x, y))));
+                        }
+                    }
+                    "orientation auto" => {
+                        let _ = tx.send(Command::Orientation(None));
+                    }
+                    _ if line.starts_with("orientation") => {
+                        if let Some(Ok(degrees)) = line.split_whitespace().nth(1).map(str::parse) {
+                            let _ = tx.send(Command::Orientation(Some(degrees)));
+                        }
+                    }
+                    _ if line.starts_with("rotate") => {
+                        if let Some(Ok(degrees)) = line.split_whitespace().nth(1).map(str::parse) {
+                            let _ = tx.send(Command::Rotate(degrees));
+                        }
+                    }
+                    "hflip on" => {
+                        let _ = tx.send(Command::HFlip(true));
+                    }
+                    "hflip off" => {
+                        let _ = tx.send(Command::HFlip(false));
+                    }
+                    "vflip on" => {
+                        let _ = tx.send(Command::VFlip(true));
+                    }
+                    "vflip off" => {
+                        let _ = tx.send(Command::VFlip(false));
+                    }
+                    _ if line.starts_with("denoise") => {
+                        if let Some(Ok(strength)) = line.split_whitespace().nth(1).map(str::parse) {
+                            let _ = tx.send(Command::Denoise(strength));
+                        }
+                    }
+                    _ if line.starts_with("sharpen") => {
+                        if let Some(Ok(amount)) = line.split_whitespace().nth(1).map(str::parse) {
+                            let _ = tx.send(Command::Sharpen(amount));
+                        }
+                    }
+                    _ if line.starts_with("deinterlace") => {
+                        match line.split_whitespace().nth(1) {
+                            Some("off") => {
+                                let _ = tx.send(Command::Deinterlace(DeinterlaceMode::Off));
+                            }
+                            Some("on") => {
+                                let _ = tx.send(Command::Deinterlace(DeinterlaceMode::On));
+                            }
+                            Some("auto") => {
+                                let _ = tx.send(Command::Deinterlace(DeinterlaceMode::Auto));
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ if line.starts_with("tonemap") => {
+                        match line.split_whitespace().nth(1) {
+                            Some("off") => {
+                                let _ = tx.send(Command::Tonemap(Tonemap::Off));
+                            }
+                            Some("hable") => {
+                                let _ = tx.send(Command::Tonemap(Tonemap::Hable));
+                            }
+                            Some("mobius") => {
+                                let _ = tx.send(Command::Tonemap(Tonemap::Mobius));
+                            }
+                            Some("reinhard") => {
+                                let _ = tx.send(Command::Tonemap(Tonemap::Reinhard));
+                            }
+                            _ => {}
+                        }
+                    }
+                    "field-rate on" => {
+                        let _ = tx.send(Command::FieldRate(true));
+                    }
+                    "field-rate off" => {
+                        let _ = tx.send(Command::FieldRate(false));
+                    }
+                    _ if line.starts_with("replaygain") => {
+                        match line.split_whitespace().nth(1) {
+                            Some("off") => {
+                                let _ = tx.send(Command::ReplayGain(ReplayGainMode::Off));
+                            }
+                            Some("track") => {
+                                let _ = tx.send(Command::ReplayGain(ReplayGainMode::Track));
+                            }
+                            Some("album") => {
+                                let _ = tx.send(Command::ReplayGain(ReplayGainMode::Album));
+                            }
+                            _ => {}
+                        }
+                    }
+                    "subs on" => {
+                        let _ = tx.send(Command::Subtitles(true));
+                    }
+                    "subs off" => {
+                        let _ = tx.send(Command::Subtitles(false));
+                    }
+                    "loop a" => {
+                        let _ = tx.send(Command::LoopA);
+                    }
+                    "loop b" => {
+                        let _ = tx.send(Command::LoopB);
+                    }
+                    "loop off" => {
+                        let _ = tx.send(Command::LoopOff);
+                    }
+                    _ if line.starts_with("on-end") => {
+                        match line.split_whitespace().nth(1) {
+                            Some("stop") => {
+                                let _ = tx.send(Command::OnEnd(EndAction::Stop));
+                            }
+                            Some("loop") => {
+                                let _ = tx.send(Command::OnEnd(EndAction::Loop));
+                            }
+                            Some("next") => {
+                                let _ = tx.send(Command::OnEnd(EndAction::Next));
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ if line.starts_with("shuffle") => {
+                        match line.split_whitespace().nth(1) {
+                            Some("on") => {
+                                let _ = tx.send(Command::Shuffle(true));
+                            }
+                            Some("off") => {
+                                let _ = tx.send(Command::Shuffle(false));
+                            }
+                            _ => {}
+                        }
+                    }
+                    "status" => {
+                        let _ = tx.send(Command::Status);
+                    }
+                    "step" => {
+                        let _ = tx.send(Command::StepForward);
+                    }
+                    "step back" => {
+                        let _ = tx.send(Command::StepBackward);
+                    }
+                    _ if line.starts_with("speed") => {
+                        let speed = line.split_whitespace().nth(1).and_then(|arg| arg.parse().ok());
+                        // "nan" and "inf" parse too
+                        if let Some(speed) = speed.filter(|speed: &f32| speed.is_finite()) {
+                            let _ = tx.send(Command::Speed(speed));
+                        }
+                    }
+                    _ if line.starts_with("goto") => {
+                        if let Some(Ok(secs)) = line.split_whitespace().nth(1).map(str::parse) {
+                            let _ = tx.send(Command::Goto(secs));
+                        }
+                    }
                     _ => {}
                 }
             }
@@ -63,5 +388,84 @@ pub enum Command {
     Shutdown,
     Play(String),
     Pause,
+    Resume,
+    // Position in seconds
     Goto(u32),
+    // Playback speed factor, clamped to 0.25x..4x
+    Speed(f32),
+    // Show a single frame, pausing playback first
+    StepForward,
+    StepBackward,
+    // Print a PlayerStatus snapshot
+    Status,
+    // Playlist, indices are positions in the list as printed by `playlist`
+    Enqueue(String),
+    Remove(usize),
+    Move(usize, usize),
+    Next,
+    Previous,
+    Repeat(RepeatMode),
+    Shuffle(bool),
+    Playlist,
+    // What to do once the current file has played out
+    OnEnd(EndAction),
+    // A-B loop: set A/B at the current position, or clear both
+    LoopA,
+    LoopB,
+    LoopOff,
+    // Stream by index or language tag ("auto" for the best one). The audio track switches right
+    // away, the video track applies to the files opened after.
+    AudioTrack(StreamSelection),
+    VideoTrack(StreamSelection),
+    // Switches right away like the audio track, `None` turns subtitles off
+    SubtitleTrack(Option<StreamSelection>),
+    // Show/hide the subtitles without touching the decoding
+    Subtitles(bool),
+    // Attach an SRT/WebVTT/ASS file to the current media, in place of its own subtitles
+    SubtitleFile(String),
+    // Offset in seconds for the attached file, positive shows the cues later
+    SubtitleDelay(f32),
+    // List the streams of the current file
+    Tracks,
+    // How the picture is fitted into the window
+    View(ViewMode),
+    // Scale on top of the view mode, 1.0 for none
+    Zoom(f32),
+    // Offset of the picture in fractions of the window size, 0 0 centers it
+    Pan(f32, f32),
+    // Software volume in dB, 0 leaves the level as decoded
+    Volume(f32),
+    Mute(bool),
+    // Which ReplayGain/R128 tag adjusts the level, if any
+    ReplayGain(ReplayGainMode),
+    // Gain in dB of a graphic EQ band, by index into `filters::EQ_BANDS`
+    Eq(usize, f32),
+    EqReset,
+    // Shelving gains in dB
+    Bass(f32),
+    Treble(f32),
+    // Extra libavfilter audio filters run after the EQ, `None` removes them
+    AudioFilter(Option<String>),
+    // Video filters, applied to the frames decoded from here on and to the files opened after.
+    // Crop is width, height, x and y in source pixels.
+    Crop(Option<(u32, u32, u32, u32)>),
+    // Clockwise, a multiple of 90 degrees, on top of the orientation
+    Rotate(u32),
+    // Clockwise degrees replacing the rotation and flips from the stream's metadata, `None` goes
+    // back to them
+    Orientation(Option<u32>),
+    HFlip(bool),
+    VFlip(bool),
+    // hqdn3d strength, 0 turns it off
+    Denoise(f32),
+    // unsharp amount, negative blurs and 0 turns it off
+    Sharpen(f32),
+    // Extra libavfilter video filters run after the rest, `None` removes them
+    VideoFilter(Option<String>),
+    // Deinterlacing off, always on, or on once the decoder flags interlaced frames
+    Deinterlace(DeinterlaceMode),
+    // Output a frame per field when deinterlacing, doubling the frame rate
+    FieldRate(bool),
+    // Curve that brings PQ/HLG video down to SDR, or off to show it as it is
+    Tonemap(Tonemap),
 }
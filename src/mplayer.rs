@@ -1,36 +1,89 @@
 use std::{
     collections::VecDeque,
+    mem,
+    path::Path,
     process,
-    sync::{ Arc, LazyLock, Mutex, RwLock, mpsc::{ Receiver, channel } },
+    ptr,
+    sync::{
+        Arc,
+        LazyLock,
+        Mutex,
+        RwLock,
+        mpsc::{ Receiver, TryRecvError, TrySendError, channel },
+    },
     thread,
     time::{ Duration, Instant },
 };
 
-use ffmpeg_next::{ frame::{ Audio, Video }, software::scaling::Flags, Frame, Rational };
+use ffmpeg_next::{
+    format::Pixel,
+    frame::{ Audio, Video },
+    rescale,
+    Rescale,
+    software::scaling::Flags,
+    Rational,
+};
 use sdl3::{
+    audio::AudioSpec,
     event::{ Event, WindowEvent },
     pixels::{ Color, PixelFormat },
-    render::{ Canvas, Texture },
+    render::{ Canvas, FRect, Texture },
     video::Window,
     EventPump,
     Sdl,
     VideoSubsystem,
 };
+use sdl3_sys::{
+    pixels::{ SDL_Colorspace, SDL_PIXELFORMAT_UNKNOWN, SDL_PixelFormat },
+    properties::{
+        SDL_CreateProperties,
+        SDL_DestroyProperties,
+        SDL_GetNumberProperty,
+        SDL_GetPointerProperty,
+        SDL_SetNumberProperty,
+    },
+    render::{
+        SDL_CreateTextureWithProperties,
+        SDL_GetRendererProperties,
+        SDL_GetTextureProperties,
+        SDL_PROP_RENDERER_TEXTURE_FORMATS_POINTER,
+        SDL_PROP_TEXTURE_COLORSPACE_NUMBER,
+        SDL_PROP_TEXTURE_CREATE_ACCESS_NUMBER,
+        SDL_PROP_TEXTURE_CREATE_COLORSPACE_NUMBER,
+        SDL_PROP_TEXTURE_CREATE_FORMAT_NUMBER,
+        SDL_PROP_TEXTURE_CREATE_HEIGHT_NUMBER,
+        SDL_PROP_TEXTURE_CREATE_WIDTH_NUMBER,
+        SDL_TEXTUREACCESS_STREAMING,
+        SDL_UpdateNVTexture,
+        SDL_UpdateYUVTexture,
+    },
+};
 
 use crate::{
     Command,
-    audio::init_audio_subsystem,
+    audio::{ ReplayGainMode, db_to_linear, init_audio_subsystem, negotiate_output_spec },
+    color::{ Colorimetry, Tonemap },
     constants::ConvFormat,
-    core::MPlayerCore,
+    core::{ DecodeThread, MILLIS, MPlayerCore, MediaThread, MediaThreadReport },
+    filters::{ AudioEffects, Deinterlace, DeinterlaceMode, EQ_BANDS, VideoEffects },
+    osd::{ Anchor, draw_lines },
+    playlist::{ Playlist, RepeatMode },
+    subtitle_files::ExternalSubtitles,
+    subtitles::{ SubtitleCue, SubtitleOverlay },
     utils::{
         MDecodeOptions,
+        MediaInfo,
         Range,
+        StreamSelection,
         clear_screen,
         convert_pts,
+        destroy_texture,
+        format_duration_ms,
         move_terminal_cursor,
         print_at_line,
         time_base_to_ns,
     },
+    view::VideoView,
 };
 use crate::{ audio::MPlayerAudio, utils::calculate_wait_from_rational };
 
@@ -52,24 +105,128 @@ pub struct MPlayer {
     pub player_frequency: i32,
     internal_buff_v: Option<VecDeque<Video>>,
     internal_buff_a: Option<VecDeque<Audio>>,
-    internal_buff_s: Option<VecDeque<Frame>>,
+    internal_buff_s: Option<VecDeque<SubtitleCue>>,
+    subtitle_overlay: SubtitleOverlay,
+    // Loaded from a file, replaces the embedded subtitles while set
+    external_subtitles: Option<ExternalSubtitles>,
+    media_thread: Option<MediaThread>,
+    paused: bool,
+    speed: f64,
+    // pts (video time base) of the frame currently on screen
+    last_pts: Option<i64>,
+    frame_step: Option<FrameStep>,
+    // Stepping moves the video away from the (paused) audio, resuming has to line them back up
+    stepped: bool,
+    playlist: Playlist,
+    // Preloaded next playlist entry and its path
+    next_media: Option<(MediaThread, String)>,
+    // Audio frames go to the device on one continuous timeline (seconds) so the stream can carry
+    // on across files. `audio_offset` maps the current file onto it, `None` until its first frame.
+    audio_offset: Option<f64>,
+    audio_sent_until: f64,
+    end_action: EndAction,
+    // Second, frame and pause state the now playing view was last drawn for
+    now_playing: Option<(u32, Option<i64>, bool)>,
+    // Stream index of the audio track the device was set up for
+    audio_track: Option<i16>,
+    // No device could be opened for the track, its frames are dropped and the system clock
+    // keeps the video going
+    audio_failed: bool,
+    // Options for the files opened from here on, the stream selection changes at runtime
+    decode_options: MDecodeOptions,
+    // The window takes the size of the next frame, after that it keeps whatever size the user
+    // gives it
+    fit_window: bool,
+    view: VideoView,
+    // Software gain in dB, applied on top of ReplayGain
+    volume: f32,
+    muted: bool,
+    replay_gain: ReplayGainMode,
+    // EQ, tone and custom filters, handed to every media thread as a filter chain
+    audio_effects: AudioEffects,
+    // Same for the picture, the chain also goes in `decode_options`
+    video_effects: VideoEffects,
+    // A-B loop points in milliseconds, playback jumps back to A when the clock reaches B
+    loop_a: Option<u32>,
+    loop_b: Option<u32>,
+    // Set while the seek back to A is on its way, the clock only moves once it lands
+    loop_seeking: bool,
+    // Entries in a row that failed to open, a list of nothing but broken files is gone through
+    // once and not over and over
+    open_failures: usize,
+}
+
+/// What the player does once the current file has played out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EndAction {
+    Stop,
+    // Start the same file over
+    Loop,
+    // Move on through the playlist, stopping at its end
+    Next,
+}
+
+enum FrameStep {
+    Forward,
+    Backward {
+        // Show the last frame before this pts
+        before: i64,
+        // Frames only count once the player went through the seek to the previous keyframe
+        seeked: bool,
+        candidate: Option<Video>,
+    },
+}
+
+/// Snapshot of what the player is doing, for the REPL and any other front end.
+#[derive(Debug, Clone)]
+pub struct PlayerStatus {
+    pub media: MediaThreadReport,
+    // Presentation clock in milliseconds
+    pub position: u32,
+    pub paused: bool,
+    pub speed: f64,
+    // A-B loop region in milliseconds, when both points are set
+    pub ab_loop: Option<(u32, u32)>,
+    // In dB, before ReplayGain
+    pub volume: f32,
+    pub muted: bool,
 }
 
 pub struct MPlayerStats {
     frame_count: u16,
     frame_count_instant: Instant,
+    dropped_frames: u32,
 }
 
 const WINDOW_WIDTH: u32 = 100;
 const WINDOW_HEIGHT: u32 = 100;
+// Window size for audio files without cover art
+const PLACEHOLDER_SIZE: (u32, u32) = (640, 360);
 
 pub static OPTS: MDecodeOptions = MDecodeOptions {
     scaling_flag: Flags::BILINEAR,
     look_range: Range { min: 10, max: 100 },
     window_default_size: (1280, 720),
     pixel_format: ffmpeg_next::format::Pixel::RGB24,
+    thread_count: 0,
+    thread_type: ffmpeg_next::codec::threading::Type::Frame,
+    video_stream: StreamSelection::Best,
+    audio_stream: StreamSelection::Best,
+    subtitle_stream: Some(StreamSelection::Best),
+    native_formats: Vec::new(),
+    audio_output: None,
+    video_filters: None,
+    deinterlace: Deinterlace {
+        mode: DeinterlaceMode::Auto,
+        double_rate: false,
+    },
+    rotation: None,
+    tonemap: Tonemap::Off,
 };
 
+// Decoder output formats that can go to a texture without conversion
+const NATIVE_CANDIDATES: [Pixel; 4] = [Pixel::YUV420P, Pixel::YUVJ420P, Pixel::NV12, Pixel::NV21];
+
 impl MPlayer {
     pub fn setup() -> Result<Self, MPlayerError> {
         let sdl_ctx = sdl3::init().map_err(|_| MPlayerError::WindowCreationFailed)?;
@@ -93,6 +250,10 @@ impl MPlayer {
             .create_texture_streaming(Some(PixelFormat::RGB24.into()), WINDOW_WIDTH, WINDOW_HEIGHT)
             .map_err(|_| MPlayerError::TextureCreationFailed)?;
 
+        let mut decode_options = OPTS.clone();
+        decode_options.native_formats = native_pixel_formats(&canvas);
+        decode_options.audio_output = Some(negotiate_output_spec(&sdl_ctx));
+
         let core = Arc::new(Mutex::new(MPlayerCore::new(Some(&OPTS))));
 
         Ok(MPlayer {
@@ -107,6 +268,7 @@ impl MPlayer {
             player_stats: MPlayerStats {
                 frame_count: 0,
                 frame_count_instant: Instant::now(),
+                dropped_frames: 0,
             },
             clock: 0.0,
             audio: None,
@@ -115,34 +277,271 @@ impl MPlayer {
             internal_buff_a: None,
             internal_buff_v: None,
             internal_buff_s: None,
+            subtitle_overlay: SubtitleOverlay::new(),
+            external_subtitles: None,
+            media_thread: None,
+            paused: false,
+            speed: 1.0,
+            last_pts: None,
+            frame_step: None,
+            stepped: false,
+            playlist: Playlist::new(),
+            next_media: None,
+            audio_offset: None,
+            audio_sent_until: 0.0,
+            end_action: EndAction::Next,
+            loop_a: None,
+            loop_b: None,
+            loop_seeking: false,
+            open_failures: 0,
+            audio_track: None,
+            audio_failed: false,
+            now_playing: None,
+            decode_options,
+            fit_window: true,
+            view: VideoView::new(),
+            volume: 0.0,
+            muted: false,
+            replay_gain: ReplayGainMode::Off,
+            audio_effects: AudioEffects::default(),
+            video_effects: VideoEffects::default(),
         })
     }
     pub fn tick(&mut self, cli_command: Option<Command>) -> () {
         if let Some(command) = cli_command {
             self.process_command(command);
         }
+        let mut reached_end = false;
+        let mut switch_to_next = false;
+        let mut resize_event = None;
+        let mut view_changed = false;
+        // A decode thread drained (or exited) and everything it produced has been taken out of
+        // the buffers
+        let mut video_exhausted = false;
+        let mut audio_exhausted = false;
         // Check if there is an active decoder and obtains the frame
-        if let Ok(lock) = &self.core.lock() {
-            // Clock
+        if let Ok(mut lock) = self.core.lock() {
             if lock.has_media {
-                let hasnt_ticket_for = self.beat.elapsed();
+                self.open_failures = 0;
+            }
+            // A seek makes everything decoded so far stale
+            if let Some(to) = lock.pending_seek.take() {
+                if let Some(ref mut buff) = self.internal_buff_v {
+                    buff.clear();
+                }
+                if let Some(ref mut buff) = self.internal_buff_a {
+                    buff.clear();
+                }
+                if let Some(video) = &lock.video {
+                    while let Ok(_) = video.output_rx.try_recv() {}
+                }
+                if let Some(audio) = &lock.audio {
+                    while let Ok(_) = audio.output_rx.try_recv() {}
+                }
+                if let Some(subtitle) = &lock.subtitle {
+                    while let Ok(_) = subtitle.output_rx.try_recv() {}
+                }
+                if let Some(ref mut buff) = self.internal_buff_s {
+                    buff.clear();
+                }
+                self.subtitle_overlay.clear();
+                if let Some(ref mut audio) = self.audio {
+                    audio.clear();
+                }
+                self.clock = convert_pts(
+                    to,
+                    rescale::TIME_BASE,
+                    Rational(1, self.player_frequency)
+                ) as f64;
+                // From where the seek landed, the clock was still at the old position above
+                if let Some(external) = &self.external_subtitles {
+                    let position = self.clock / (self.player_frequency as f64);
+                    external.queue(position, self.internal_buff_s.get_or_insert_with(VecDeque::new));
+                }
+                self.beat = Instant::now();
+                if let Some(FrameStep::Backward { ref mut seeked, .. }) = self.frame_step {
+                    *seeked = true;
+                }
+                self.loop_seeking = false;
+            }
+            // Nothing is coming from a seek that failed, stepping back and the A-B loop would
+            // wait for it forever
+            if mem::take(&mut lock.seek_failed) {
+                if let Some(FrameStep::Backward { .. }) = self.frame_step {
+                    self.frame_step = None;
+                }
+                self.loop_seeking = false;
+            }
+
+            // Handle audio first, it drives the clock. Frames go to the device as soon as they
+            // are decoded, the SDL stream and the audio channel do the buffering.
+            if let Some(audio) = &lock.audio {
+                // A new track may not fit the device that is open
+                if self.audio_track != Some(audio.stream_info.index) {
+                    self.audio_track = Some(audio.stream_info.index);
+                    let fits = match (&self.audio, device_spec(&self.decode_options, audio)) {
+                        (Some(mplayer_audio), Some(spec)) => mplayer_audio.accepts(&spec),
+                        _ => false,
+                    };
+                    if !fits {
+                        self.audio = None;
+                        self.audio_failed = false;
+                    }
+                }
+                if let Some(ref mut buff) = self.internal_buff_a {
+                    if buff.len() < 10 {
+                        // Read before the channel, so an empty channel means everything was taken
+                        let drained = audio.is_drained();
+                        let received = audio.output_rx.try_recv();
+                        audio_exhausted =
+                            buff.is_empty() &&
+                            received.is_err() &&
+                            (drained || matches!(received, Err(TryRecvError::Disconnected)));
+                        if let Ok(mut frame) = received {
+                            // Move the frame onto the continuous timeline the device works with
+                            if let Some(pts) = frame.pts() {
+                                let secs = (pts as f64) * f64::from(audio.stream_info.time_base);
+                                let offset = *self.audio_offset.get_or_insert(
+                                    self.audio_sent_until - secs
+                                );
+                                let continuous = secs + offset;
+                                frame.set_pts(
+                                    Some((continuous * (rescale::TIME_BASE.1 as f64)) as i64)
+                                );
+                                self.audio_sent_until =
+                                    continuous +
+                                    ((frame.samples() as f64) * self.speed) / (frame.rate() as f64);
+                            }
+                            buff.push_back(frame);
+                        }
+                    } else {
+                        // println!("buffer cap reached");
+                    }
+                } else {
+                    self.internal_buff_a = Some(VecDeque::new());
+                }
+                if self.audio.is_none() && !self.audio_failed {
+                    let opened = device_spec(&self.decode_options, audio)
+                        .ok_or("no output format for the track".to_string())
+                        .and_then(|spec| {
+                            init_audio_subsystem(&self.sdl, spec).map_err(|err| err.to_string())
+                        });
+                    match opened {
+                        Ok(mut mplayer_audio) => {
+                            mplayer_audio.set_speed(self.speed);
+                            self.audio = Some(mplayer_audio);
+                        }
+                        Err(err) => {
+                            print_at_line(format!("[audio] unable to play: {}", err), 0, 9);
+                            self.audio_failed = true;
+                        }
+                    }
+                }
+                if self.audio_failed && let Some(ref mut buff) = self.internal_buff_a {
+                    buff.clear();
+                }
+                // Follows volume changes and the tags of whatever file is playing
+                let gain = self.output_gain(lock.media_info.as_ref());
+                if let Some(ref mut mplayer_audio) = self.audio {
+                    mplayer_audio.set_gain(gain);
+                }
                 if
-                    hasnt_ticket_for.as_nanos() >
-                    time_base_to_ns(Rational(1, self.player_frequency))
+                    let Some(ref mut buff) = self.internal_buff_a &&
+                    let Some(mplayer_audio) = &self.audio
                 {
-                    self.clock +=
-                        (hasnt_ticket_for.as_nanos() as f64) /
-                        (time_base_to_ns(Rational(1, self.player_frequency)) as f64);
+                    while let Some(frame) = buff.pop_front() {
+                        let pts = frame.pts();
+                        match mplayer_audio.try_send(frame) {
+                            Ok(()) => {
+                                if let Some(pts) = pts {
+                                    print_at_line(
+                                        format!(
+                                            "audio pts: {}",
+                                            convert_pts(
+                                                pts,
+                                                rescale::TIME_BASE,
+                                                Rational(1, self.player_frequency)
+                                            )
+                                        ),
+                                        0,
+                                        3
+                                    );
+                                }
+                            }
+                            Err(TrySendError::Full(frame)) => {
+                                buff.push_front(frame);
+                                break;
+                            }
+                            Err(TrySendError::Disconnected(_)) => {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Clock: the audio device is the master whenever it is playing something, the
+            // system clock takes over when there is no audio (or none has been played yet).
+            if lock.has_media && !self.paused {
+                // Negative while the tail of the previous file is still playing out
+                let audio_clock = self.audio
+                    .as_ref()
+                    .and_then(|audio| audio.position())
+                    .zip(self.audio_offset)
+                    .map(|(position, offset)| position - offset);
+                if let Some(position) = audio_clock {
+                    self.clock = position * (self.player_frequency as f64);
                     self.beat = Instant::now();
-                    print_at_line(format!("clock: {}", self.clock), 0, 0);
+                } else {
+                    let hasnt_ticket_for = self.beat.elapsed();
+                    if
+                        hasnt_ticket_for.as_nanos() >
+                        time_base_to_ns(Rational(1, self.player_frequency))
+                    {
+                        self.clock +=
+                            ((hasnt_ticket_for.as_nanos() as f64) /
+                                (time_base_to_ns(Rational(1, self.player_frequency)) as f64)) *
+                            self.speed;
+                        self.beat = Instant::now();
+                    }
+                }
+                print_at_line(format!("clock: {}", self.clock), 0, 0);
+
+                if
+                    !self.loop_seeking &&
+                    let (Some(a), Some(b)) = (self.loop_a, self.loop_b) &&
+                    self.position_ms() >= b &&
+                    let Some(media_thread) = &self.media_thread
+                {
+                    media_thread.seek((a as i64).rescale(MILLIS, rescale::TIME_BASE));
+                    self.loop_seeking = true;
+                }
+            }
+
+            // Subtitles are few and small, everything decoded waits in the buffer until it is due
+            if let Some(subtitle) = &lock.subtitle {
+                let buff = self.internal_buff_s.get_or_insert_with(VecDeque::new);
+                while let Ok(cue) = subtitle.output_rx.try_recv() {
+                    let at = buff.partition_point(|queued| queued.start <= cue.start);
+                    buff.insert(at, cue);
                 }
             }
+            if let Some(ref mut buff) = self.internal_buff_s {
+                let position = self.clock / (self.player_frequency as f64);
+                self.subtitle_overlay.update(position, buff);
+            }
 
-            // Handle video
+            // Handle video, frames follow the clock
             if let Some(video) = &lock.video {
                 if let Some(ref mut buff) = self.internal_buff_v {
                     if buff.len() < 10 {
-                        if let Ok(frame) = video.output_rx.try_recv() {
+                        let drained = video.is_drained();
+                        let received = video.output_rx.try_recv();
+                        video_exhausted =
+                            buff.is_empty() &&
+                            received.is_err() &&
+                            (drained || matches!(received, Err(TryRecvError::Disconnected)));
+                        if let Ok(frame) = received {
                             buff.push_back(frame);
                         }
                     }
@@ -150,125 +549,199 @@ impl MPlayer {
                     self.internal_buff_v = Some(VecDeque::new());
                 }
 
+                let frequency = Rational(1, self.player_frequency);
+                let time_base = video.stream_info.time_base;
+                let due = |frame: &Video| {
+                    frame.pts().map(|pts| convert_pts(pts, time_base, frequency) as f64)
+                };
+
+                if let Some(ref mut buff) = self.internal_buff_v && self.frame_step.is_none() {
+                    // Without an audio clock there is nothing to line up the first frame with,
+                    // so jump to it instead of waiting for the stream's start offset.
+                    if
+                        self.audio.is_none() &&
+                        let Some(Some(pts)) = buff.front().map(due) &&
+                        pts - self.clock > (self.player_frequency as f64)
+                    {
+                        self.clock = pts;
+                    }
+                    // Drop the frames the clock already went past, only the latest due one is
+                    // worth presenting.
+                    while buff.len() > 1 && let Some(Some(next)) = buff.get(1).map(due) {
+                        if next > self.clock {
+                            break;
+                        }
+                        buff.pop_front();
+                        self.player_stats.dropped_frames += 1;
+                    }
+                }
+
                 if
+                    let Some(ref mut buff) = self.internal_buff_v &&
+                    let Some(step) = self.frame_step.take()
+                {
+                    match step {
+                        FrameStep::Forward => {
+                            if let Some(mut frame) = buff.pop_front() {
+                                view_changed |= Self::present_frame(
+                                    &mut self.canvas,
+                                    &mut self.video_texture,
+                                    &mut self.subtitle_overlay,
+                                    &mut self.fit_window,
+                                    &mut self.view,
+                                    &mut frame
+                                );
+                                if let Some(pts) = frame.pts() {
+                                    self.clock = convert_pts(pts, time_base, frequency) as f64;
+                                    self.last_pts = Some(pts);
+                                }
+                            } else {
+                                // Nothing decoded yet, try again next tick
+                                self.frame_step = Some(FrameStep::Forward);
+                            }
+                        }
+                        FrameStep::Backward { before, seeked, mut candidate } => {
+                            // Decode forward from the keyframe, keeping the latest frame that
+                            // comes before the one that was on screen.
+                            let mut done = false;
+                            while seeked && let Some(frame) = buff.pop_front() {
+                                match frame.pts() {
+                                    Some(pts) if pts >= before => {
+                                        let mut shown = match candidate.take() {
+                                            Some(candidate) => {
+                                                buff.push_front(frame);
+                                                candidate
+                                            }
+                                            // Already at the first frame
+                                            None => frame,
+                                        };
+                                        view_changed |= Self::present_frame(
+                                            &mut self.canvas,
+                                            &mut self.video_texture,
+                                            &mut self.subtitle_overlay,
+                                            &mut self.fit_window,
+                                            &mut self.view,
+                                            &mut shown
+                                        );
+                                        if let Some(pts) = shown.pts() {
+                                            self.clock = convert_pts(
+                                                pts,
+                                                time_base,
+                                                frequency
+                                            ) as f64;
+                                            self.last_pts = Some(pts);
+                                        }
+                                        done = true;
+                                        break;
+                                    }
+                                    _ => {
+                                        candidate = Some(frame);
+                                    }
+                                }
+                            }
+                            if !done {
+                                self.frame_step = Some(FrameStep::Backward {
+                                    before,
+                                    seeked,
+                                    candidate,
+                                });
+                            }
+                        }
+                    }
+                } else if
                     let Some(ref mut buff) = self.internal_buff_v &&
                     let Some(frame) = buff.front() &&
                     let Some(pts) = frame.pts()
                 {
-                    if pts == 0 {
-                        self.clock = 0.0;
-                    }
                     if
-                        (
-                            convert_pts(
-                                pts,
-                                video.stream_info.time_base,
-                                Rational(1, self.player_frequency)
-                            ) as f64
-                        ) <= self.clock &&
+                        (convert_pts(pts, time_base, frequency) as f64) <= self.clock &&
                         let Some(ref mut frame) = buff.pop_front()
                     {
                         self.player_stats.frame_count += 1;
                         if self.player_stats.frame_count_instant.elapsed().as_secs_f64() > 1.0 {
-                            print_at_line(format!("fps: {}", self.player_stats.frame_count), 0, 4);
+                            let interlaced = self.media_thread
+                                .as_ref()
+                                .is_some_and(|media_thread| media_thread.interlaced());
+                            print_at_line(
+                                format!(
+                                    "fps: {} dropped: {} deinterlace: {}",
+                                    self.player_stats.frame_count,
+                                    self.player_stats.dropped_frames,
+                                    self.decode_options.deinterlace.state(interlaced)
+                                ),
+                                0,
+                                4
+                            );
                             self.player_stats.frame_count_instant = Instant::now();
                             self.player_stats.frame_count = 0;
                         }
 
                         print_at_line(
-                            format!(
-                                "video pts: {}",
-                                convert_pts(
-                                    pts,
-                                    video.stream_info.time_base,
-                                    Rational(1, self.player_frequency)
-                                )
-                            ),
+                            format!("video pts: {}", convert_pts(pts, time_base, frequency)),
                             0,
                             2
                         );
                         // println!("[video] {}", frame.pts().unwrap());
-                        let size = (frame.width(), frame.height());
-                        if size != self.canvas.output_size().unwrap() {
-                            let _ = self.canvas
-                                .window_mut()
-                                .set_size(frame.width(), frame.height());
-                            self.video_texture = self.canvas
-                                .texture_creator()
-                                .create_texture_streaming(
-                                    Some(frame.format().convert().into()),
-                                    size.0,
-                                    size.1
-                                )
-                                .unwrap();
-                            print_at_line("resized".to_string(), 0, 10);
-                        }
-                        let _ = self.video_texture.with_lock(None, |buffer: &mut [u8], _| {
-                            let frame_data = frame.data_mut(0);
-                            buffer.swap_with_slice(frame_data);
-                        });
-
-                        let _ = self.canvas.copy(&self.video_texture, None, None);
-                        self.canvas.present();
+                        view_changed |= Self::present_frame(
+                            &mut self.canvas,
+                            &mut self.video_texture,
+                            &mut self.subtitle_overlay,
+                            &mut self.fit_window,
+                            &mut self.view,
+                            frame
+                        );
+                        self.last_pts = Some(pts);
                     } else {
                     }
                 }
             }
-            // Handle audio here
-            if let Some(audio) = &lock.audio {
-                if let Some(ref mut buff) = self.internal_buff_a {
-                    if buff.len() < 10 {
-                        if let Ok(frame) = audio.output_rx.try_recv() {
-                            buff.push_back(frame);
-                        }
-                    } else {
-                        // println!("buffer cap reached");
-                    }
-                } else {
-                    self.internal_buff_a = Some(VecDeque::new());
-                }
-                if
-                    let Some(ref mut buff) = self.internal_buff_a &&
-                    let Some(frame) = buff.front() &&
-                    let Some(pts) = frame.pts()
-                {
-                    if pts == 0 {
-                        self.clock = 0.0;
-                    }
-                    if
-                        (
-                            convert_pts(
-                                pts,
-                                audio.stream_info.time_base,
-                                Rational(1, self.player_frequency)
-                            ) as f64
-                        ) <= self.clock &&
-                        let Some(frame) = buff.pop_front()
-                    {
-                        print_at_line(
-                            format!(
-                                "audio pts: {}",
-                                convert_pts(
-                                    pts,
-                                    audio.stream_info.time_base,
-                                    Rational(1, self.player_frequency)
-                                )
-                            ),
-                            0,
-                            3
-                        );
-                        if let Some(audio) = &self.audio {
-                            let _ = audio.tx.send(frame);
-                        } else {
-                            self.audio = Some(
-                                init_audio_subsystem(
-                                    &self.sdl,
-                                    audio.stream_info.audio_spec.clone().unwrap()
-                                ).unwrap()
-                            );
-                        }
-                    }
+
+            // Audio-only files get their cover art (or a placeholder) with the title and position
+            let audio_only =
+                lock.has_media &&
+                lock.audio.is_some() &&
+                lock.video.as_ref().map_or(true, |video| video.stream_info.attached_pic);
+            if audio_only {
+                let shown = (self.position_ms() / 1000, self.last_pts, self.paused);
+                if self.now_playing != Some(shown) || self.subtitle_overlay.is_dirty() {
+                    self.now_playing = Some(shown);
+                    let title = lock.media_info
+                        .as_ref()
+                        .and_then(|info| info.title.clone())
+                        .or_else(|| {
+                            self.playlist
+                                .current()
+                                .and_then(|path| Path::new(path).file_name())
+                                .map(|name| name.to_string_lossy().to_string())
+                        })
+                        .unwrap_or_default();
+                    let duration = self.media_thread
+                        .as_ref()
+                        .and_then(|media_thread| media_thread.report())
+                        .and_then(|report| report.duration)
+                        .map(format_duration_ms)
+                        .unwrap_or("--:--".to_string());
+                    let position = format!(
+                        "{} / {}{}",
+                        format_duration_ms(self.position_ms()),
+                        duration,
+                        if self.paused { " (paused)" } else { "" }
+                    );
+                    Self::present_now_playing(
+                        &mut self.canvas,
+                        &self.video_texture,
+                        &mut self.subtitle_overlay,
+                        &self.view,
+                        lock.video.is_some() && self.last_pts.is_some(),
+                        &[&title, &position]
+                    );
                 }
+            } else if self.subtitle_overlay.is_dirty() {
+                // A cue came or went between frames (or while paused), the texture still holds
+                // the frame on screen
+                let picture = Self::draw_video(&mut self.canvas, &self.video_texture, &self.view);
+                self.subtitle_overlay.draw(&mut self.canvas, Some(picture));
+                self.canvas.present();
             }
 
             for event in self.sdl_event_pump.poll_iter() {
@@ -280,14 +753,421 @@ impl MPlayer {
                     }
                     Event::Window { timestamp, window_id, win_event } =>
                         match win_event {
-                            sdl3::event::WindowEvent::Resized(w, h) => {
+                            WindowEvent::Resized(..) | WindowEvent::PixelSizeChanged(..) => {
                                 self.beat = Instant::now();
+                                resize_event = Some(win_event);
                             }
                             _ => {}
                         }
                     _ => {}
                 }
             }
+
+            if
+                lock.has_media &&
+                let Some(media_thread) = &self.media_thread &&
+                let Some(report) = media_thread.report() &&
+                report.eof
+            {
+                // Open the next entry while this one plays out, so its decoders are primed by
+                // the time the switch happens.
+                if
+                    self.end_action == EndAction::Next &&
+                    self.next_media.is_none() &&
+                    let Some(path) = self.playlist.peek_next().cloned()
+                {
+                    let next_media = MPlayerCore::open_media(
+                        path.clone(),
+                        Some(self.decode_options_for(&path)),
+                        Arc::clone(&self.core),
+                        true
+                    );
+                    next_media.set_speed(self.speed);
+                    next_media.set_audio_filters(self.audio_effects.filter_chain());
+                    self.next_media = Some((next_media, path));
+                }
+
+                // The file is done once the demuxer hit the end, the decoders drained and every
+                // frame they produced has been shown/played.
+                let video_done = lock.video.is_none() || video_exhausted;
+                let audio_done = lock.audio.is_none() || audio_exhausted;
+                let audio_idle = self.audio.as_ref().map_or(true, |audio| audio.is_idle());
+                let next_ready = self.next_media
+                    .as_ref()
+                    .and_then(|(next_media, _)| next_media.report())
+                    .map_or(false, |report| report.opened);
+                // With the next file ready there is no need to wait for the audio to play
+                // out, its frames queue up right behind the ones still on the device.
+                switch_to_next = video_done && audio_done && next_ready;
+                reached_end = video_done && audio_done && audio_idle;
+            }
+        }
+
+        if let Some(event) = resize_event {
+            self.handle_resize(event);
+        } else if view_changed {
+            self.apply_view();
+        }
+        let open_failed = self.media_thread
+            .as_ref()
+            .and_then(|media_thread| media_thread.report())
+            .map_or(false, |report| report.failed());
+        if switch_to_next {
+            self.switch_to_next();
+        } else if reached_end {
+            self.on_media_end();
+        } else if open_failed {
+            self.on_open_failed();
+        }
+    }
+
+    fn switch_to_next(&mut self) {
+        let Some((media_thread, path)) = self.next_media.take() else {
+            return;
+        };
+        // The playlist may have been edited since the entry was preloaded
+        let expected = self.playlist.next(false).cloned();
+        if expected.as_ref() != Some(&path) {
+            media_thread.exit();
+            self.drop_next_media();
+            match expected {
+                Some(path) => self.open(path),
+                None => self.stop(),
+            }
+            return;
+        }
+
+        if let Some(old) = self.media_thread.take() {
+            old.exit();
+        }
+        if let Ok(mut lock) = self.core.lock() {
+            lock.video = lock.next_video.take();
+            lock.audio = lock.next_audio.take();
+            lock.subtitle = lock.next_subtitle.take();
+            lock.media_info = media_thread.report().and_then(|report| report.media_info);
+            lock.has_media = lock.video.is_some() || lock.audio.is_some();
+            lock.pending_seek = None;
+            lock.seek_failed = false;
+            let reuse = match (&self.audio, &lock.audio) {
+                (Some(mplayer_audio), Some(audio)) =>
+                    device_spec(&self.decode_options, audio).map_or(false, |spec| {
+                        mplayer_audio.accepts(&spec)
+                    }),
+                _ => false,
+            };
+            if !reuse {
+                self.audio = None;
+                self.audio_failed = false;
+            }
+        }
+        media_thread.promote();
+        self.media_thread = Some(media_thread);
+        self.clear_loop();
+        // Cues of the previous file are on its timeline
+        self.external_subtitles = Self::sidecar_subtitles(&path);
+
+        // The new file starts once what is left of the previous one has played out
+        let tail = self.audio
+            .as_ref()
+            .and_then(|audio| audio.position())
+            .map_or(0.0, |position| (self.audio_sent_until - position).max(0.0));
+        if self.audio.is_none() {
+            self.audio_sent_until = 0.0;
+        }
+        // Picked up again from the first frame of the new file
+        self.audio_offset = None;
+        self.clock = -tail * (self.player_frequency as f64);
+        self.last_pts = None;
+        self.frame_step = None;
+        self.stepped = false;
+        self.fit_window = true;
+        self.beat = Instant::now();
+        // The cues still queued belong to the previous file
+        if let Some(ref mut buff) = self.internal_buff_s {
+            buff.clear();
+        }
+        self.subtitle_overlay.clear();
+        self.queue_external_subtitles();
+    }
+
+    fn drop_next_media(&mut self) {
+        if let Some((next_media, _)) = self.next_media.take() {
+            next_media.exit();
+        }
+        if let Ok(mut lock) = self.core.lock() {
+            lock.next_video = None;
+            lock.next_audio = None;
+            lock.next_subtitle = None;
+        }
+    }
+
+    /// End of media event: fired once the last frame of the file has been shown and its audio has
+    /// played out.
+    fn on_media_end(&mut self) {
+        print_at_line(format!("[end of media] {:?}", self.end_action), 0, 9);
+        match self.end_action {
+            EndAction::Stop => self.stop(),
+            EndAction::Loop => {
+                if let Some(media_thread) = &self.media_thread {
+                    media_thread.seek(0);
+                }
+            }
+            EndAction::Next => {
+                match self.playlist.next(false).cloned() {
+                    Some(path) => self.open(path),
+                    None => self.stop(),
+                }
+            }
+        }
+    }
+
+    // Skips to the next entry, even on repeat-one as the same file would only fail again
+    fn on_open_failed(&mut self) {
+        let path = self.playlist.current().cloned().unwrap_or_default();
+        print_at_line(format!("[open] unable to play {}", path), 0, 9);
+        self.open_failures += 1;
+        if self.open_failures >= self.playlist.entries().len() {
+            self.open_failures = 0;
+            self.stop();
+            return;
+        }
+        match self.playlist.next(true).cloned() {
+            Some(path) => self.open(path),
+            None => self.stop(),
+        }
+    }
+
+    fn open(&mut self, path: String) {
+        self.stop();
+        self.external_subtitles = Self::sidecar_subtitles(&path);
+        let media_thread = MPlayerCore::open_media(
+            path.clone(),
+            Some(self.decode_options_for(&path)),
+            Arc::clone(&self.core),
+            false
+        );
+        media_thread.set_speed(self.speed);
+        media_thread.set_audio_filters(self.audio_effects.filter_chain());
+        self.media_thread = Some(media_thread);
+        self.queue_external_subtitles();
+    }
+
+    // A subtitle file next to the media takes the place of the embedded subtitles
+    fn decode_options_for(&self, path: &str) -> MDecodeOptions {
+        let mut decode_options = self.decode_options.clone();
+        if ExternalSubtitles::find_next_to(Path::new(path)).is_some() {
+            decode_options.subtitle_stream = None;
+        }
+        decode_options
+    }
+
+    fn sidecar_subtitles(path: &str) -> Option<ExternalSubtitles> {
+        let sidecar = ExternalSubtitles::find_next_to(Path::new(path))?;
+        match ExternalSubtitles::load(&sidecar) {
+            Ok(external) => {
+                print_at_line(
+                    format!("[subtitles] {} cues from {}", external.cues.len(), sidecar.display()),
+                    0,
+                    9
+                );
+                Some(external)
+            }
+            Err(err) => {
+                print_at_line(format!("[subtitles] {}: {:?}", sidecar.display(), err), 0, 9);
+                None
+            }
+        }
+    }
+
+    // Puts the external cues from the current position on in the subtitle buffer
+    // Without an external file the queue holds the embedded cues, which stay as they are
+    fn queue_external_subtitles(&mut self) {
+        let Some(external) = &self.external_subtitles else {
+            return;
+        };
+        if let Some(ref mut buff) = self.internal_buff_s {
+            buff.clear();
+        }
+        self.subtitle_overlay.clear();
+        let position = self.clock / (self.player_frequency as f64);
+        external.queue(position, self.internal_buff_s.get_or_insert_with(VecDeque::new));
+    }
+
+    fn stop(&mut self) {
+        if let Some(media_thread) = self.media_thread.take() {
+            media_thread.exit();
+        }
+        self.clear_loop();
+        self.drop_next_media();
+        if let Ok(mut lock) = self.core.lock() {
+            lock.video = None;
+            lock.audio = None;
+            lock.subtitle = None;
+            lock.media_info = None;
+            lock.has_media = false;
+            lock.pending_seek = None;
+            lock.seek_failed = false;
+        }
+        if let Some(ref mut buff) = self.internal_buff_v {
+            buff.clear();
+        }
+        if let Some(ref mut buff) = self.internal_buff_a {
+            buff.clear();
+        }
+        if let Some(ref mut buff) = self.internal_buff_s {
+            buff.clear();
+        }
+        self.subtitle_overlay.clear();
+        self.external_subtitles = None;
+        // Reopened on the first audio frame, with the new stream's spec
+        self.audio = None;
+        self.audio_track = None;
+        self.audio_failed = false;
+        self.now_playing = None;
+        self.fit_window = true;
+        self.audio_offset = None;
+        self.audio_sent_until = 0.0;
+        self.clock = 0.0;
+        self.paused = false;
+        self.last_pts = None;
+        self.frame_step = None;
+        self.stepped = false;
+        self.beat = Instant::now();
+    }
+
+    fn present_now_playing(
+        canvas: &mut Canvas<Window>,
+        video_texture: &Texture,
+        subtitle_overlay: &mut SubtitleOverlay,
+        view: &VideoView,
+        has_cover: bool,
+        lines: &[&str]
+    ) {
+        let mut picture = None;
+        if has_cover {
+            picture = Some(Self::draw_video(canvas, video_texture, view));
+        } else {
+            if canvas.output_size().map_or(true, |size| size.1 < PLACEHOLDER_SIZE.1) {
+                let _ = canvas.window_mut().set_size(PLACEHOLDER_SIZE.0, PLACEHOLDER_SIZE.1);
+            }
+            let draw_color = canvas.draw_color();
+            canvas.set_draw_color(Color::RGB(20, 20, 20));
+            canvas.clear();
+            canvas.set_draw_color(draw_color);
+        }
+        let size = canvas.output_size().unwrap_or(PLACEHOLDER_SIZE);
+        draw_lines(canvas, lines, size, if has_cover { Anchor::Bottom } else { Anchor::Center });
+        subtitle_overlay.draw(canvas, picture);
+        canvas.present();
+    }
+
+    fn clear_loop(&mut self) {
+        self.loop_a = None;
+        self.loop_b = None;
+        self.loop_seeking = false;
+    }
+
+    // Returns whether the picture's display aspect changed, the decoder then has to scale to a
+    // different size
+    fn present_frame(
+        canvas: &mut Canvas<Window>,
+        video_texture: &mut Texture,
+        subtitle_overlay: &mut SubtitleOverlay,
+        fit_window: &mut bool,
+        view: &mut VideoView,
+        frame: &mut Video
+    ) -> bool {
+        let format = frame.format().convert();
+        let size = (frame.width(), frame.height());
+        let display_aspect = VideoView::display_aspect(size.0, size.1, frame.aspect_ratio());
+        // The decoder keeps the pixel aspect up to date through filtering and scaling, so the
+        // frame's shape follows crops and rotations. Scaling rounds, so tiny differences are
+        // left alone rather than asking for yet another size.
+        let aspect_changed = match (display_aspect, view.display_aspect) {
+            (Some(aspect), Some(shown)) => (aspect - shown).abs() > shown * 0.001,
+            (aspect, shown) => aspect.is_some() != shown.is_some(),
+        };
+        if aspect_changed {
+            view.display_aspect = display_aspect;
+        }
+        let colorspace = frame_colorspace(frame);
+        let query = video_texture.query();
+        if
+            (query.format, query.width, query.height) != (format, size.0, size.1) ||
+            texture_colorspace(video_texture) != colorspace
+        {
+            let texture = create_video_texture(canvas, format, size, colorspace).unwrap();
+            destroy_texture(mem::replace(video_texture, texture));
+            print_at_line("resized".to_string(), 0, 10);
+        }
+        if *fit_window {
+            // The default height at the picture's display aspect, whatever size the frames
+            // come at
+            let height = OPTS.window_default_size.1;
+            let aspect = view.display_aspect.unwrap_or((size.0 as f32) / (size.1.max(1) as f32));
+            let _ = canvas.window_mut().set_size(((height as f32) * aspect).round() as u32, height);
+            *fit_window = false;
+        }
+        Self::upload_frame(video_texture, frame);
+
+        let picture = Self::draw_video(canvas, video_texture, view);
+        subtitle_overlay.draw(canvas, Some(picture));
+        canvas.present();
+        aspect_changed
+    }
+
+    // Clears the canvas and draws the texture where the view puts it, returns that rectangle
+    fn draw_video(canvas: &mut Canvas<Window>, video_texture: &Texture, view: &VideoView) -> FRect {
+        let output = canvas.output_size().unwrap_or(OPTS.window_default_size);
+        let query = video_texture.query();
+        let picture = view.rect(output, (query.width, query.height));
+        let draw_color = canvas.draw_color();
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        canvas.set_draw_color(draw_color);
+        let _ = canvas.copy(video_texture, None, picture);
+        picture
+    }
+
+    // Copies every plane of the frame into the texture, going by the frame's linesizes since
+    // FFmpeg pads rows for alignment
+    fn upload_frame(video_texture: &mut Texture, frame: &Video) {
+        let uploaded = match frame.format() {
+            // SDL reads (height + 1) / 2 chroma rows, which the sdl3 wrapper's length checks get
+            // wrong for odd heights, so the planes go in as they are
+            Pixel::YUV420P | Pixel::YUVJ420P => unsafe {
+                SDL_UpdateYUVTexture(
+                    video_texture.raw(),
+                    ptr::null(),
+                    frame.data(0).as_ptr(),
+                    frame.stride(0) as i32,
+                    frame.data(1).as_ptr(),
+                    frame.stride(1) as i32,
+                    frame.data(2).as_ptr(),
+                    frame.stride(2) as i32
+                )
+                    .then_some(())
+                    .ok_or_else(|| sdl3::get_error().to_string())
+            }
+            Pixel::NV12 | Pixel::NV21 => unsafe {
+                SDL_UpdateNVTexture(
+                    video_texture.raw(),
+                    ptr::null(),
+                    frame.data(0).as_ptr(),
+                    frame.stride(0) as i32,
+                    frame.data(1).as_ptr(),
+                    frame.stride(1) as i32
+                )
+                    .then_some(())
+                    .ok_or_else(|| sdl3::get_error().to_string())
+            }
+            _ =>
+                video_texture
+                    .update(None, frame.data(0), frame.stride(0))
+                    .map_err(|err| err.to_string()),
+        };
+        if let Err(err) = uploaded {
+            print_at_line(format!("[video] unable to upload the frame: {}", err), 0, 10);
         }
     }
 
@@ -299,13 +1179,505 @@ impl MPlayer {
                 std::process::exit(0);
             }
             Command::Play(path) => {
-                MPlayerCore::open_media(path, Some(OPTS.clone()), Arc::clone(&self.core));
-                self.beat = Instant::now();
+                let index = self.playlist.enqueue(path);
+                if let Some(path) = self.playlist.jump(index).cloned() {
+                    self.open(path);
+                }
+            }
+            Command::Enqueue(path) => {
+                let index = self.playlist.enqueue(path);
+                if
+                    self.media_thread.is_none() &&
+                    let Some(path) = self.playlist.jump(index).cloned()
+                {
+                    self.open(path);
+                }
+            }
+            Command::Remove(index) => {
+                if self.playlist.remove(index) {
+                    match self.playlist.next(true).cloned() {
+                        Some(path) => self.open(path),
+                        None => self.stop(),
+                    }
+                }
+            }
+            Command::Move(from, to) => {
+                self.playlist.move_entry(from, to);
+            }
+            Command::Next => {
+                if let Some(path) = self.playlist.next(true).cloned() {
+                    self.open(path);
+                }
+            }
+            Command::Previous => {
+                if let Some(path) = self.playlist.previous().cloned() {
+                    self.open(path);
+                }
+            }
+            Command::OnEnd(action) => {
+                self.end_action = action;
+                if action != EndAction::Next {
+                    self.drop_next_media();
+                }
+            }
+            Command::Repeat(mode) => {
+                self.playlist.repeat = mode;
+            }
+            Command::Shuffle(shuffle) => {
+                self.playlist.set_shuffle(shuffle);
+            }
+            Command::Playlist => {
+                let current = self.playlist.current_index();
+                for (index, entry) in self.playlist.entries().iter().enumerate() {
+                    let marker = if Some(index) == current { ">" } else { " " };
+                    println!("{} {}: {}", marker, index, entry);
+                }
+                println!(
+                    "repeat: {:?} shuffle: {}",
+                    self.playlist.repeat,
+                    self.playlist.shuffle()
+                );
+            }
+            Command::Pause => {
+                if let Some(media_thread) = &self.media_thread && !self.paused {
+                    media_thread.pause();
+                    if let Some(audio) = &self.audio {
+                        let _ = audio.device.pause();
+                    }
+                    self.paused = true;
+                }
+            }
+            Command::Resume => {
+                if let Some(media_thread) = &self.media_thread && self.paused {
+                    media_thread.play();
+                    if let Some(audio) = &self.audio {
+                        let _ = audio.device.resume();
+                    }
+                    self.paused = false;
+                    self.frame_step = None;
+                    if self.stepped {
+                        media_thread.seek(self.position_av());
+                        self.stepped = false;
+                    }
+                    // Time spent paused must not be added to the clock
+                    self.beat = Instant::now();
+                }
+            }
+            Command::StepForward => {
+                if self.media_thread.is_some() {
+                    self.process_command(Command::Pause);
+                    self.frame_step = Some(FrameStep::Forward);
+                    self.stepped = true;
+                }
+            }
+            Command::StepBackward => {
+                let time_base = self.core
+                    .lock()
+                    .ok()
+                    .and_then(|lock| lock.video.as_ref().map(|video| video.stream_info.time_base));
+                if
+                    let Some(media_thread) = &self.media_thread &&
+                    let Some(time_base) = time_base &&
+                    let Some(pts) = self.last_pts
+                {
+                    // Lands on the keyframe before the current frame, even if that one is a
+                    // keyframe itself
+                    media_thread.seek(pts.rescale(time_base, rescale::TIME_BASE) - 1);
+                    self.frame_step = Some(FrameStep::Backward {
+                        before: pts,
+                        seeked: false,
+                        candidate: None,
+                    });
+                    self.stepped = true;
+                    self.process_command(Command::Pause);
+                }
+            }
+            Command::Speed(speed) => {
+                // NaN would get through the clamp
+                if !speed.is_finite() {
+                    println!("speed has to be a number");
+                    return;
+                }
+                self.speed = (speed as f64).clamp(0.25, 4.0);
+                if let Some(ref mut audio) = self.audio {
+                    audio.set_speed(self.speed);
+                }
+                if let Some(media_thread) = &self.media_thread {
+                    media_thread.set_speed(self.speed);
+                    // Seeking in place flushes the audio decoded at the old tempo, otherwise
+                    // the change would only be heard a couple of seconds later.
+                    media_thread.seek(self.position_av());
+                }
+            }
+            Command::Volume(db) => {
+                if !db.is_finite() {
+                    println!("volume has to be a number of dB");
+                    return;
+                }
+                self.volume = db.clamp(-60.0, 12.0);
+            }
+            Command::Mute(muted) => {
+                self.muted = muted;
+            }
+            Command::ReplayGain(mode) => {
+                self.replay_gain = mode;
+            }
+            Command::Eq(band, db) => {
+                let mut effects = self.audio_effects.clone();
+                match effects.eq.get_mut(band) {
+                    Some(gain) => {
+                        *gain = db.clamp(-24.0, 24.0);
+                    }
+                    None => println!("no EQ band {}, there are {}", band, EQ_BANDS.len()),
+                }
+                self.set_audio_effects(effects);
+            }
+            Command::EqReset => {
+                let effects = AudioEffects { eq: [0.0; 10], ..self.audio_effects.clone() };
+                self.set_audio_effects(effects);
+            }
+            Command::Bass(db) => {
+                let effects = AudioEffects {
+                    bass: db.clamp(-24.0, 24.0),
+                    ..self.audio_effects.clone()
+                };
+                self.set_audio_effects(effects);
+            }
+            Command::Treble(db) => {
+                let effects = AudioEffects {
+                    treble: db.clamp(-24.0, 24.0),
+                    ..self.audio_effects.clone()
+                };
+                self.set_audio_effects(effects);
+            }
+            Command::AudioFilter(filters) => {
+                let effects = AudioEffects { custom: filters, ..self.audio_effects.clone() };
+                self.set_audio_effects(effects);
+            }
+            Command::Crop(crop) => {
+                let effects = VideoEffects { crop, ..self.video_effects.clone() };
+                self.set_video_effects(effects);
+            }
+            Command::Rotate(degrees) => {
+                if degrees % 90 != 0 {
+                    println!("rotation has to be a multiple of 90 degrees");
+                    return;
+                }
+                let effects = VideoEffects { rotation: degrees % 360, ..self.video_effects.clone() };
+                self.fit_window |= effects != self.video_effects;
+                self.set_video_effects(effects);
+            }
+            Command::HFlip(hflip) => {
+                let effects = VideoEffects { hflip, ..self.video_effects.clone() };
+                self.set_video_effects(effects);
+            }
+            Command::VFlip(vflip) => {
+                let effects = VideoEffects { vflip, ..self.video_effects.clone() };
+                self.set_video_effects(effects);
+            }
+            Command::Denoise(strength) => {
+                let effects = VideoEffects {
+                    denoise: strength.clamp(0.0, 20.0),
+                    ..self.video_effects.clone()
+                };
+                self.set_video_effects(effects);
+            }
+            Command::Sharpen(amount) => {
+                let effects = VideoEffects {
+                    sharpen: amount.clamp(-2.0, 5.0),
+                    ..self.video_effects.clone()
+                };
+                self.set_video_effects(effects);
+            }
+            Command::VideoFilter(filters) => {
+                let effects = VideoEffects { custom: filters, ..self.video_effects.clone() };
+                self.set_video_effects(effects);
+            }
+            Command::Deinterlace(mode) => {
+                let deinterlace = Deinterlace { mode, ..self.decode_options.deinterlace };
+                self.set_deinterlace(deinterlace);
+            }
+            Command::Orientation(rotation) => {
+                if rotation.is_some_and(|degrees| degrees % 90 != 0) {
+                    println!("rotation has to be a multiple of 90 degrees");
+                    return;
+                }
+                let rotation = rotation.map(|degrees| degrees % 360);
+                if rotation != self.decode_options.rotation {
+                    self.decode_options.rotation = rotation;
+                    // Sideways turns swap the picture's sides, the window follows
+                    self.fit_window = true;
+                    if let Some((next_media, _)) = &self.next_media {
+                        next_media.set_rotation(rotation);
+                    }
+                    if let Some(media_thread) = &self.media_thread {
+                        media_thread.set_rotation(rotation);
+                        media_thread.seek(self.position_av());
+                    }
+                }
+            }
+            Command::Tonemap(tonemap) => {
+                if tonemap != Tonemap::Off && !Tonemap::available() {
+                    println!("tonemapping needs an FFmpeg built with zimg, zscale is missing");
+                } else if tonemap != self.decode_options.tonemap {
+                    self.decode_options.tonemap = tonemap;
+                    if let Some((next_media, _)) = &self.next_media {
+                        next_media.set_tonemap(tonemap);
+                    }
+                    if let Some(media_thread) = &self.media_thread {
+                        media_thread.set_tonemap(tonemap);
+                        media_thread.seek(self.position_av());
+                    }
+                }
+            }
+            Command::FieldRate(double_rate) => {
+                let deinterlace = Deinterlace { double_rate, ..self.decode_options.deinterlace };
+                self.set_deinterlace(deinterlace);
+            }
+            Command::Status => {
+                match self.status() {
+                    Some(status) => {
+                        let duration = status.media.duration
+                            .map(|duration| format!("{:.2}s", (duration as f64) / 1000.0))
+                            .unwrap_or("unknown".to_string());
+                        let ab_loop = status.ab_loop
+                            .map(|(a, b)|
+                                format!("{:.2}s - {:.2}s", (a as f64) / 1000.0, (b as f64) / 1000.0)
+                            )
+                            .unwrap_or("off".to_string());
+                        print_at_line(
+                            format!(
+                                "[status] {:?} position: {:.2}s / {} buffered: {:.2}s - {:.2}s eof: {} paused: {} speed: {}x loop: {} volume: {:+.1}dB{}",
+                                status.media.status,
+                                (status.position as f64) / 1000.0,
+                                duration,
+                                (status.media.buffered.min as f64) / 1000.0,
+                                (status.media.buffered.max as f64) / 1000.0,
+                                status.media.eof,
+                                status.paused,
+                                status.speed,
+                                ab_loop,
+                                status.volume,
+                                if status.muted { " (muted)" } else { "" }
+                            ),
+                            0,
+                            8
+                        );
+                    }
+                    None => print_at_line("[status] no media".to_string(), 0, 8),
+                }
+            }
+            Command::AudioTrack(selection) => {
+                self.decode_options.audio_stream = selection.clone();
+                if let Some(media_thread) = &self.media_thread {
+                    media_thread.select_audio(selection);
+                    media_thread.seek(self.position_av());
+                }
+            }
+            Command::SubtitleTrack(selection) => {
+                self.decode_options.subtitle_stream = selection.clone();
+                // Back to the embedded ones
+                self.external_subtitles = None;
+                if let Some(media_thread) = &self.media_thread {
+                    media_thread.select_subtitle(selection);
+                    // Cues between here and where the demuxer got to would be missed otherwise
+                    media_thread.seek(self.position_av());
+                }
+            }
+            Command::SubtitleFile(path) => {
+                match ExternalSubtitles::load(Path::new(&path)) {
+                    Ok(external) => {
+                        print_at_line(
+                            format!("[subtitles] {} cues from {}", external.cues.len(), path),
+                            0,
+                            9
+                        );
+                        self.external_subtitles = Some(external);
+                        if let Some(media_thread) = &self.media_thread {
+                            media_thread.select_subtitle(None);
+                        }
+                        self.queue_external_subtitles();
+                    }
+                    Err(err) => print_at_line(format!("[subtitles] {}: {:?}", path, err), 0, 9),
+                }
+            }
+            Command::SubtitleDelay(secs) => {
+                if let Some(ref mut external) = self.external_subtitles {
+                    external.offset = secs as f64;
+                    print_at_line(format!("[subtitles] delay: {:.2}s", external.offset), 0, 9);
+                }
+                self.queue_external_subtitles();
+            }
+            Command::View(mode) => {
+                self.view.mode = mode;
+                self.apply_view();
+            }
+            Command::Zoom(zoom) => {
+                if !zoom.is_finite() {
+                    println!("zoom has to be a number");
+                    return;
+                }
+                self.view.zoom = zoom.clamp(0.1, 10.0);
+                self.apply_view();
+            }
+            Command::Pan(x, y) => {
+                if !x.is_finite() || !y.is_finite() {
+                    println!("pan has to be two numbers");
+                    return;
+                }
+                self.view.pan = (x, y);
+                self.apply_view();
+            }
+            Command::Subtitles(visible) => {
+                self.subtitle_overlay.set_visible(visible);
+            }
+            Command::VideoTrack(selection) => {
+                // Takes effect from the next file, same as picking it before opening
+                self.decode_options.video_stream = selection;
+            }
+            Command::Tracks => {
+                match self.media_thread.as_ref().and_then(|media_thread| media_thread.report()) {
+                    Some(report) => {
+                        for track in report.tracks {
+                            let active =
+                                Some(track.index) == report.video_track ||
+                                Some(track.index) == report.audio_track ||
+                                Some(track.index) == report.subtitle_track;
+                            println!(
+                                "{} {}: {:?} {} {}",
+                                if active { ">" } else { " " },
+                                track.index,
+                                track.kind,
+                                track.codec,
+                                track.language.unwrap_or("und".to_string())
+                            );
+                        }
+                    }
+                    None => println!("no media"),
+                }
+            }
+            Command::LoopA => {
+                if self.media_thread.is_some() {
+                    let a = self.position_ms();
+                    self.loop_a = Some(a);
+                    // A B that is not after A any more has to be set again
+                    if self.loop_b.is_some_and(|b| b <= a) {
+                        self.loop_b = None;
+                    }
+                    print_at_line(format!("[loop] A: {:.2}s", (a as f64) / 1000.0), 0, 9);
+                }
+            }
+            Command::LoopB => {
+                let b = self.position_ms();
+                match self.loop_a {
+                    Some(a) if b > a => {
+                        self.loop_b = Some(b);
+                        self.loop_seeking = false;
+                        print_at_line(
+                            format!(
+                                "[loop] {:.2}s - {:.2}s",
+                                (a as f64) / 1000.0,
+                                (b as f64) / 1000.0
+                            ),
+                            0,
+                            9
+                        );
+                    }
+                    Some(_) => print_at_line("[loop] B has to come after A".to_string(), 0, 9),
+                    None => print_at_line("[loop] set A first".to_string(), 0, 9),
+                }
+            }
+            Command::LoopOff => {
+                self.clear_loop();
+                print_at_line("[loop] off".to_string(), 0, 9);
+            }
+            Command::Goto(secs) => {
+                if let Some(media_thread) = &self.media_thread {
+                    media_thread.seek((secs as i64) * (rescale::TIME_BASE.1 as i64));
+                }
             }
-            _ => {}
         }
     }
 
+    pub fn status(&self) -> Option<PlayerStatus> {
+        let media = self.media_thread.as_ref()?.report()?;
+        Some(PlayerStatus {
+            media,
+            position: self.position_ms(),
+            paused: self.paused,
+            speed: self.speed,
+            ab_loop: self.loop_a.zip(self.loop_b),
+            volume: self.volume,
+            muted: self.muted,
+        })
+    }
+
+    // Hands a changed effects chain to the media thread and, like a speed change, seeks in place
+    // so the audio already decoded without it is dropped
+    fn set_audio_effects(&mut self, effects: AudioEffects) {
+        if effects == self.audio_effects {
+            return;
+        }
+        self.audio_effects = effects;
+        if let Some((next_media, _)) = &self.next_media {
+            next_media.set_audio_filters(self.audio_effects.filter_chain());
+        }
+        if let Some(media_thread) = &self.media_thread {
+            media_thread.set_audio_filters(self.audio_effects.filter_chain());
+            media_thread.seek(self.position_av());
+        }
+    }
+
+    // Like `set_audio_effects`, the seek also brings up a frame with the new filters when paused
+    fn set_video_effects(&mut self, effects: VideoEffects) {
+        if effects == self.video_effects {
+            return;
+        }
+        self.video_effects = effects;
+        self.decode_options.video_filters = self.video_effects.filter_chain();
+        if let Some((next_media, _)) = &self.next_media {
+            next_media.set_video_filters(self.decode_options.video_filters.clone());
+        }
+        if let Some(media_thread) = &self.media_thread {
+            media_thread.set_video_filters(self.decode_options.video_filters.clone());
+            media_thread.seek(self.position_av());
+        }
+    }
+
+    // Same as `set_video_effects`, for the deinterlacer in front of the filters
+    fn set_deinterlace(&mut self, deinterlace: Deinterlace) {
+        if deinterlace == self.decode_options.deinterlace {
+            return;
+        }
+        self.decode_options.deinterlace = deinterlace;
+        if let Some((next_media, _)) = &self.next_media {
+            next_media.set_deinterlace(deinterlace);
+        }
+        if let Some(media_thread) = &self.media_thread {
+            media_thread.set_deinterlace(deinterlace);
+            media_thread.seek(self.position_av());
+        }
+    }
+
+    // Linear gain for the device: the volume plus the file's ReplayGain when it is enabled
+    fn output_gain(&self, media_info: Option<&MediaInfo>) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        let replay_gain = media_info.map_or(0.0, |info| info.replay_gain.gain_db(self.replay_gain));
+        db_to_linear(self.volume + replay_gain)
+    }
+
+    // Current clock position in milliseconds
+    fn position_ms(&self) -> u32 {
+        ((self.clock.max(0.0) * 1000.0) / (self.player_frequency as f64)) as u32
+    }
+
+    // Current clock position in AV_TIME_BASE units
+    fn position_av(&self) -> i64 {
+        ((self.clock / (self.player_frequency as f64)) * (rescale::TIME_BASE.1 as f64)) as i64
+    }
+
     pub fn go(&mut self, commander: Receiver<Command>, tps: i32) {
         let (tick_tx, tick_rx) = channel::<()>();
         self.player_frequency = tps;
@@ -349,10 +1721,35 @@ impl MPlayer {
         let _ = timer_t.join();
     }
     fn handle_resize(&mut self, event: WindowEvent) -> () {
-        if let WindowEvent::Resized(w, h) = event {
-            if let Ok(lock) = self.core.lock() {
-            }
+        if let WindowEvent::Resized(..) | WindowEvent::PixelSizeChanged(..) = event {
+            self.apply_view();
+        }
+    }
+
+    // Gets the decoder scaling to the picture's size on screen and redraws with the new layout
+    fn apply_view(&mut self) {
+        // In pixels, which is more than the window size on high DPI displays
+        let Ok(output) = self.canvas.output_size() else {
+            return;
+        };
+        let query = self.video_texture.query();
+        let (width, height) = self.view.scale_size(output, (query.width, query.height));
+        if let Some(media_thread) = &self.media_thread {
+            media_thread.resize(width, height);
+        }
+        // Frames at the new size take a moment to come through, until then the one on screen
+        // gets stretched
+        if
+            let Ok(lock) = self.core.lock() &&
+            lock.video.as_ref().is_some_and(|video| !video.stream_info.attached_pic) &&
+            self.last_pts.is_some()
+        {
+            let picture = Self::draw_video(&mut self.canvas, &self.video_texture, &self.view);
+            self.subtitle_overlay.draw(&mut self.canvas, Some(picture));
+            self.canvas.present();
         }
+        // The now playing view draws itself again on the next tick
+        self.now_playing = None;
     }
 }
 
@@ -368,3 +1765,77 @@ pub enum MPlayerError {
     SDLInitError,
     UnknownError,
 }
+
+// What the device has to be opened with to play `audio`'s frames
+fn device_spec(decode_options: &MDecodeOptions, audio: &DecodeThread<Audio>) -> Option<AudioSpec> {
+    decode_options.audio_output.clone().or_else(|| audio.stream_info.audio_spec.clone())
+}
+
+// YUV frames come straight from the decoder and the texture converts them with their matrix,
+// anything else was converted to RGB by swscale already
+fn frame_colorspace(frame: &Video) -> SDL_Colorspace {
+    if NATIVE_CANDIDATES.contains(&frame.format()) {
+        Colorimetry::of(frame).sdl_colorspace().unwrap_or(SDL_Colorspace::BT601_LIMITED)
+    } else {
+        SDL_Colorspace::SRGB
+    }
+}
+
+// `create_texture_streaming` with a colorspace, which the sdl3 crate has no way to pass
+fn create_video_texture(
+    canvas: &Canvas<Window>,
+    format: PixelFormat,
+    size: (u32, u32),
+    colorspace: SDL_Colorspace
+) -> Result<Texture, MPlayerError> {
+    unsafe {
+        let properties = SDL_CreateProperties();
+        let values = [
+            (SDL_PROP_TEXTURE_CREATE_FORMAT_NUMBER, SDL_PixelFormat::from(format).0 as i64),
+            (SDL_PROP_TEXTURE_CREATE_ACCESS_NUMBER, SDL_TEXTUREACCESS_STREAMING.0 as i64),
+            (SDL_PROP_TEXTURE_CREATE_WIDTH_NUMBER, size.0 as i64),
+            (SDL_PROP_TEXTURE_CREATE_HEIGHT_NUMBER, size.1 as i64),
+            (SDL_PROP_TEXTURE_CREATE_COLORSPACE_NUMBER, colorspace.0 as i64),
+        ];
+        for (name, value) in values {
+            SDL_SetNumberProperty(properties, name, value);
+        }
+        let raw = SDL_CreateTextureWithProperties(canvas.raw(), properties);
+        SDL_DestroyProperties(properties);
+        if raw.is_null() {
+            return Err(MPlayerError::TextureCreationFailed);
+        }
+        Ok(canvas.texture_creator().raw_create_texture(raw))
+    }
+}
+
+fn texture_colorspace(texture: &Texture) -> SDL_Colorspace {
+    unsafe {
+        let properties = SDL_GetTextureProperties(texture.raw());
+        SDL_Colorspace(
+            SDL_GetNumberProperty(properties, SDL_PROP_TEXTURE_COLORSPACE_NUMBER, 0) as u32
+        )
+    }
+}
+
+// The formats in `NATIVE_CANDIDATES` the renderer accepts for textures
+fn native_pixel_formats(canvas: &Canvas<Window>) -> Vec<Pixel> {
+    let mut supported = Vec::new();
+    unsafe {
+        let properties = SDL_GetRendererProperties(canvas.raw());
+        let formats = SDL_GetPointerProperty(
+            properties,
+            SDL_PROP_RENDERER_TEXTURE_FORMATS_POINTER,
+            ptr::null_mut()
+        ) as *const SDL_PixelFormat;
+        // Terminated by SDL_PIXELFORMAT_UNKNOWN
+        let mut index = 0;
+        while !formats.is_null() && *formats.add(index) != SDL_PIXELFORMAT_UNKNOWN {
+            supported.push(*formats.add(index));
+            index += 1;
+        }
+    }
+    NATIVE_CANDIDATES.into_iter()
+        .filter(|pixel| supported.contains(&SDL_PixelFormat::from(pixel.convert())))
+        .collect()
+}
@@ -0,0 +1,45 @@
+use std::ffi::CString;
+
+use sdl3::{ pixels::Color, render::{ BlendMode, Canvas, FRect }, video::Window };
+use sdl3_sys::render::{ SDL_DEBUG_TEXT_FONT_CHARACTER_SIZE, SDL_RenderDebugText };
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    Center,
+    Bottom,
+}
+
+/// Horizontally centered lines in SDL's built-in debug font, on a translucent box so they stay
+/// readable over video. The font only covers ASCII, anything else shows up as a placeholder glyph.
+pub fn draw_lines(canvas: &mut Canvas<Window>, lines: &[&str], size: (u32, u32), anchor: Anchor) {
+    if lines.is_empty() {
+        return;
+    }
+    let scale = ((size.1 / 240).max(1)) as f32;
+    let glyph = SDL_DEBUG_TEXT_FONT_CHARACTER_SIZE as f32;
+    let line_height = glyph * 1.5;
+    // Positions below are in scaled units
+    let (width, height) = ((size.0 as f32) / scale, (size.1 as f32) / scale);
+    let top = match anchor {
+        Anchor::Center => (height - (lines.len() as f32) * line_height) / 2.0,
+        Anchor::Bottom => height - glyph * 2.0 - ((lines.len() as f32) - 1.0) * line_height,
+    };
+    let draw_color = canvas.draw_color();
+    let _ = canvas.set_scale(scale, scale);
+    canvas.set_blend_mode(BlendMode::Blend);
+    for (row, line) in lines.iter().enumerate() {
+        let text_width = (line.chars().count() as f32) * glyph;
+        let x = ((width - text_width) / 2.0).max(0.0);
+        let y = top + (row as f32) * line_height;
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+        let _ = canvas.fill_rect(FRect::new(x - 2.0, y - 2.0, text_width + 4.0, glyph + 4.0));
+        canvas.set_draw_color(Color::RGB(255, 255, 255));
+        if let Ok(text) = CString::new(*line) {
+            unsafe {
+                SDL_RenderDebugText(canvas.raw(), x, y, text.as_ptr());
+            }
+        }
+    }
+    let _ = canvas.set_scale(1.0, 1.0);
+    canvas.set_draw_color(draw_color);
+}
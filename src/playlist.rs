@@ -0,0 +1,340 @@
+use rand::{ Rng, seq::SliceRandom };
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepeatMode {
+    Off,
+    One,
+    All,
+}
+
+/// Ordered list of media paths. Entries keep the order they were enqueued/moved in, the play
+/// order is kept separately so shuffling never touches what the user sees.
+pub struct Playlist {
+    entries: Vec<String>,
+    // Indices into `entries`, in the order they will be played
+    order: Vec<usize>,
+    // Index into `order` of the entry that is playing
+    cursor: Option<usize>,
+    pub repeat: RepeatMode,
+    shuffle: bool,
+}
+
+impl Playlist {
+    pub fn new() -> Playlist {
+        Playlist {
+            entries: Vec::new(),
+            order: Vec::new(),
+            cursor: None,
+            repeat: RepeatMode::Off,
+            shuffle: false,
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn shuffle(&self) -> bool {
+        self.shuffle
+    }
+
+    /// Index into `entries` of the entry that is playing.
+    pub fn current_index(&self) -> Option<usize> {
+        self.cursor.map(|cursor| self.order[cursor])
+    }
+
+    pub fn current(&self) -> Option<&String> {
+        self.current_index().map(|index| &self.entries[index])
+    }
+
+    pub fn enqueue(&mut self, path: String) -> usize {
+        let index = self.entries.len();
+        self.entries.push(path);
+        if self.shuffle {
+            // Anywhere among the entries that have not been played yet
+            let from = self.cursor.map_or(0, |cursor| cursor + 1);
+            let at = rand::rng().random_range(from..=self.order.len());
+            self.order.insert(at, index);
+        } else {
+            self.order.push(index);
+        }
+        index
+    }
+
+    /// Makes `index` the current entry and returns its path.
+    pub fn jump(&mut self, index: usize) -> Option<&String> {
+        let cursor = self.order.iter().position(|&entry| entry == index)?;
+        self.cursor = Some(cursor);
+        self.current()
+    }
+
+    /// Removes the entry at `index`, returns whether it was the one playing.
+    pub fn remove(&mut self, index: usize) -> bool {
+        if index >= self.entries.len() {
+            return false;
+        }
+        let was_current = self.current_index() == Some(index);
+        self.entries.remove(index);
+        let position = self.order
+            .iter()
+            .position(|&entry| entry == index)
+            .unwrap();
+        self.order.remove(position);
+        for entry in self.order.iter_mut() {
+            if *entry > index {
+                *entry -= 1;
+            }
+        }
+        // Entries after the removed one slide back, the cursor follows whatever it pointed at.
+        // When the current entry itself goes, the cursor ends up right before its successor.
+        self.cursor = match self.cursor {
+            Some(cursor) if position < cursor => Some(cursor - 1),
+            Some(cursor) if position == cursor => cursor.checked_sub(1),
+            cursor => cursor,
+        };
+        was_current
+    }
+
+    /// Moves the entry at `from` so it ends up at `to`.
+    pub fn move_entry(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.entries.len() || to >= self.entries.len() {
+            return false;
+        }
+        let entry = self.entries.remove(from);
+        self.entries.insert(to, entry);
+        let remap = |index: usize| {
+            if index == from {
+                to
+            } else if from < to && index > from && index <= to {
+                index - 1
+            } else if to < from && index >= to && index < from {
+                index + 1
+            } else {
+                index
+            }
+        };
+        for entry in self.order.iter_mut() {
+            *entry = remap(*entry);
+        }
+        // Without shuffle the play order is the list order
+        if !self.shuffle {
+            let current = self.current_index();
+            self.order.sort();
+            self.cursor = current;
+        }
+        true
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        if self.shuffle == shuffle {
+            return;
+        }
+        self.shuffle = shuffle;
+        let current = self.current_index();
+        if shuffle {
+            self.order.shuffle(&mut rand::rng());
+            // Keep the playing entry first so the whole list plays before anything repeats
+            if let Some(current) = current {
+                let position = self.order
+                    .iter()
+                    .position(|&entry| entry == current)
+                    .unwrap();
+                self.order.swap(0, position);
+                self.cursor = Some(0);
+            }
+        } else {
+            self.order.sort();
+            self.cursor = current;
+        }
+    }
+
+    /// Moves to the entry after the current one. `user` is set when the user asked for it, in
+    /// which case repeat-one does not hold playback on the same entry.
+    pub fn next(&mut self, user: bool) -> Option<&String> {
+        if self.order.is_empty() {
+            return None;
+        }
+        let next = match self.cursor {
+            Some(cursor) if self.repeat == RepeatMode::One && !user => cursor,
+            Some(cursor) if cursor + 1 < self.order.len() => cursor + 1,
+            // The end of the list, the cursor stays on the last entry
+            Some(_) if self.repeat == RepeatMode::Off => {
+                return None;
+            }
+            Some(_) => {
+                // Wrapping around, a new round gets a new order
+                if self.shuffle {
+                    self.order.shuffle(&mut rand::rng());
+                }
+                0
+            }
+            None => 0,
+        };
+        self.cursor = Some(next);
+        self.current()
+    }
+
+    /// Entry an automatic `next` would move to, when that can be known ahead of time (wrapping
+    /// around a shuffled list draws a new order).
+    pub fn peek_next(&self) -> Option<&String> {
+        let cursor = self.cursor?;
+        let next = if self.repeat == RepeatMode::One {
+            cursor
+        } else if cursor + 1 < self.order.len() {
+            cursor + 1
+        } else if self.repeat == RepeatMode::All && !self.shuffle {
+            0
+        } else {
+            return None;
+        };
+        Some(&self.entries[self.order[next]])
+    }
+
+    pub fn previous(&mut self) -> Option<&String> {
+        let previous = match self.cursor {
+            Some(0) if self.repeat == RepeatMode::All => self.order.len() - 1,
+            Some(0) | None => 0,
+            Some(cursor) => cursor - 1,
+        };
+        if self.order.is_empty() {
+            return None;
+        }
+        self.cursor = Some(previous);
+        self.current()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_entries(entries: &[&str]) -> Playlist {
+        let mut playlist = Playlist::new();
+        for entry in entries {
+            playlist.enqueue(entry.to_string());
+        }
+        playlist
+    }
+
+    fn next(playlist: &mut Playlist, user: bool) -> Option<&str> {
+        playlist.next(user).map(String::as_str)
+    }
+
+    fn previous(playlist: &mut Playlist) -> Option<&str> {
+        playlist.previous().map(String::as_str)
+    }
+
+    #[test]
+    fn next_without_repeat_stops_on_the_last_entry() {
+        let mut playlist = with_entries(&["a", "b"]);
+        assert_eq!(next(&mut playlist, false), Some("a"));
+        assert_eq!(next(&mut playlist, false), Some("b"));
+        assert_eq!(next(&mut playlist, false), None);
+        assert_eq!(playlist.current_index(), Some(1));
+        // Entries added later pick up from there
+        playlist.enqueue("c".to_string());
+        assert_eq!(next(&mut playlist, false), Some("c"));
+    }
+
+    #[test]
+    fn next_on_an_empty_list() {
+        let mut playlist = Playlist::new();
+        assert_eq!(next(&mut playlist, false), None);
+        assert_eq!(previous(&mut playlist), None);
+        assert_eq!(playlist.current_index(), None);
+    }
+
+    #[test]
+    fn repeat_one_holds_automatic_next_only() {
+        let mut playlist = with_entries(&["a", "b"]);
+        playlist.repeat = RepeatMode::One;
+        playlist.jump(0);
+        assert_eq!(next(&mut playlist, false), Some("a"));
+        assert_eq!(playlist.peek_next().map(String::as_str), Some("a"));
+        assert_eq!(next(&mut playlist, true), Some("b"));
+    }
+
+    #[test]
+    fn repeat_all_wraps_both_ways() {
+        let mut playlist = with_entries(&["a", "b", "c"]);
+        playlist.repeat = RepeatMode::All;
+        playlist.jump(2);
+        assert_eq!(playlist.peek_next().map(String::as_str), Some("a"));
+        assert_eq!(next(&mut playlist, false), Some("a"));
+        assert_eq!(previous(&mut playlist), Some("c"));
+    }
+
+    #[test]
+    fn previous_without_repeat_stays_on_the_first_entry() {
+        let mut playlist = with_entries(&["a", "b"]);
+        playlist.jump(1);
+        assert_eq!(previous(&mut playlist), Some("a"));
+        assert_eq!(previous(&mut playlist), Some("a"));
+    }
+
+    #[test]
+    fn shuffle_keeps_the_current_entry() {
+        let entries: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+        let mut playlist = Playlist::new();
+        for entry in &entries {
+            playlist.enqueue(entry.clone());
+        }
+        playlist.jump(7);
+        playlist.set_shuffle(true);
+        assert_eq!(playlist.current_index(), Some(7));
+        // Everything else plays before the list runs out
+        let mut played = vec![7];
+        while playlist.next(false).is_some() {
+            played.extend(playlist.current_index());
+        }
+        played.sort();
+        assert_eq!(played, (0..20).collect::<Vec<_>>());
+        // The entries the user sees keep their order
+        assert_eq!(playlist.entries(), &entries[..]);
+
+        playlist.jump(3);
+        playlist.set_shuffle(false);
+        assert_eq!(playlist.current_index(), Some(3));
+        assert_eq!(next(&mut playlist, false), Some("4"));
+    }
+
+    #[test]
+    fn remove_before_the_cursor() {
+        let mut playlist = with_entries(&["a", "b", "c"]);
+        playlist.jump(1);
+        assert!(!playlist.remove(0));
+        assert_eq!(playlist.current().map(String::as_str), Some("b"));
+        assert_eq!(next(&mut playlist, false), Some("c"));
+    }
+
+    #[test]
+    fn remove_at_the_cursor() {
+        let mut playlist = with_entries(&["a", "b", "c"]);
+        playlist.jump(1);
+        assert!(playlist.remove(1));
+        // The next entry is the one after the removed one
+        assert_eq!(next(&mut playlist, false), Some("c"));
+
+        let mut playlist = with_entries(&["a", "b"]);
+        playlist.jump(0);
+        assert!(playlist.remove(0));
+        assert_eq!(playlist.current_index(), None);
+        assert_eq!(next(&mut playlist, false), Some("b"));
+    }
+
+    #[test]
+    fn remove_after_the_cursor() {
+        let mut playlist = with_entries(&["a", "b", "c"]);
+        playlist.jump(1);
+        assert!(!playlist.remove(2));
+        assert_eq!(playlist.current().map(String::as_str), Some("b"));
+        assert_eq!(next(&mut playlist, false), None);
+    }
+
+    #[test]
+    fn remove_out_of_range() {
+        let mut playlist = with_entries(&["a"]);
+        assert!(!playlist.remove(1));
+        assert_eq!(playlist.entries().len(), 1);
+    }
+}
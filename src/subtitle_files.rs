@@ -0,0 +1,299 @@
+use std::{ collections::VecDeque, fs, io, path::{ Path, PathBuf } };
+
+use crate::subtitles::{ SubtitleCue, SubtitleItem, strip_ass_tags };
+
+// Checked in this order when looking for a file next to the media
+const EXTENSIONS: [&str; 4] = ["srt", "vtt", "ass", "ssa"];
+
+#[derive(Debug)]
+pub enum SubtitleFileError {
+    Io(io::Error),
+    UnsupportedFormat,
+    // Nothing in the file could be read as a cue
+    NoCues,
+}
+
+/// Subtitles loaded from a file, shown instead of the embedded ones.
+pub struct ExternalSubtitles {
+    pub path: PathBuf,
+    pub cues: Vec<SubtitleCue>,
+    // Seconds added to every cue, positive shows them later
+    pub offset: f64,
+}
+
+impl ExternalSubtitles {
+    pub fn load(path: &Path) -> Result<ExternalSubtitles, SubtitleFileError> {
+        let bytes = fs::read(path).map_err(SubtitleFileError::Io)?;
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        Ok(ExternalSubtitles {
+            path: path.to_path_buf(),
+            cues: parse(&bytes, extension.as_deref())?,
+            offset: 0.0,
+        })
+    }
+
+    /// Looks for a subtitle file with the same base name as `media`, e.g. `movie.srt` for
+    /// `movie.mkv`.
+    pub fn find_next_to(media: &Path) -> Option<PathBuf> {
+        EXTENSIONS.iter()
+            .map(|extension| media.with_extension(extension))
+            .find(|candidate| candidate.is_file())
+    }
+
+    /// Fills `queue` with the cues still to come (or on screen) at `position`, with the offset
+    /// applied.
+    pub fn queue(&self, position: f64, queue: &mut VecDeque<SubtitleCue>) {
+        queue.clear();
+        let cues = self.cues
+            .iter()
+            .filter(move |cue| cue.end.map_or(true, |end| end + self.offset > position))
+            .map(|cue| SubtitleCue {
+                start: cue.start + self.offset,
+                end: cue.end.map(|end| end + self.offset),
+                items: cue.items.clone(),
+            });
+        queue.extend(cues);
+    }
+}
+
+// The cues in a file's contents, in order, the extension tells the format
+fn parse(bytes: &[u8], extension: Option<&str>) -> Result<Vec<SubtitleCue>, SubtitleFileError> {
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut cues = match extension {
+        Some("srt") | Some("vtt") => parse_timed_blocks(&text),
+        Some("ass") | Some("ssa") => parse_ass(&text),
+        _ => {
+            return Err(SubtitleFileError::UnsupportedFormat);
+        }
+    };
+    if cues.is_empty() {
+        return Err(SubtitleFileError::NoCues);
+    }
+    cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(cues)
+}
+
+// SRT and WebVTT share the layout: an optional identifier, a "start --> end" line and the text
+// up to the next blank line. WebVTT header, NOTE and STYLE blocks have no timing line and drop out.
+fn parse_timed_blocks(text: &str) -> Vec<SubtitleCue> {
+    let mut cues = Vec::new();
+    for block in text.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some(timing) = lines.next() else {
+            continue;
+        };
+        let mut times = timing.split("-->");
+        let start = times.next().and_then(|start| parse_timestamp(start.trim()));
+        // WebVTT puts cue settings after the end time
+        let end = times
+            .next()
+            .and_then(|end| end.split_whitespace().next())
+            .and_then(parse_timestamp);
+        let (Some(start), Some(end)) = (start, end) else {
+            continue;
+        };
+        let content = lines.map(strip_markup).collect::<Vec<_>>().join("\n");
+        if content.trim().is_empty() {
+            continue;
+        }
+        cues.push(SubtitleCue {
+            start,
+            end: Some(end),
+            items: vec![SubtitleItem::Text(content.trim().to_string())],
+        });
+    }
+    cues
+}
+
+fn parse_ass(text: &str) -> Vec<SubtitleCue> {
+    let mut cues = Vec::new();
+    let mut in_events = false;
+    // Field names of the Dialogue lines, from the section's Format line
+    let mut format: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+        if let Some(fields) = line.strip_prefix("Format:") {
+            format = fields
+                .split(',')
+                .map(|field| field.trim().to_ascii_lowercase())
+                .collect();
+            continue;
+        }
+        let Some(dialogue) = line.strip_prefix("Dialogue:") else {
+            continue;
+        };
+        if format.is_empty() {
+            continue;
+        }
+        // The text is last and may contain commas itself
+        let values: Vec<&str> = dialogue.splitn(format.len(), ',').collect();
+        let field = |name: &str| {
+            format
+                .iter()
+                .position(|field| field == name)
+                .and_then(|index| values.get(index))
+                .map(|value| value.trim())
+        };
+        let start = field("start").and_then(parse_timestamp);
+        let end = field("end").and_then(parse_timestamp);
+        let (Some(start), Some(end), Some(text)) = (start, end, field("text")) else {
+            continue;
+        };
+        let content = strip_ass_tags(text);
+        if content.is_empty() {
+            continue;
+        }
+        cues.push(SubtitleCue {
+            start,
+            end: Some(end),
+            items: vec![SubtitleItem::Text(content)],
+        });
+    }
+    cues
+}
+
+// "01:02:03,456" (SRT), "01:02:03.456" or "02:03.456" (WebVTT), "1:02:03.45" (ASS), in seconds
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let timestamp = timestamp.replace(',', ".");
+    let mut seconds = 0.0;
+    for part in timestamp.split(':') {
+        seconds = seconds * 60.0 + part.trim().parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+// Drops HTML-like tags (<i>, <font ...>, WebVTT's <c.class>) and ASS overrides some SRT files use
+fn strip_markup(line: &str) -> String {
+    let mut plain = String::new();
+    let mut in_tag = false;
+    for c in line.chars() {
+        match c {
+            '<' | '{' => {
+                in_tag = true;
+            }
+            '>' | '}' => {
+                in_tag = false;
+            }
+            _ if !in_tag => plain.push(c),
+            _ => {}
+        }
+    }
+    plain.replace("&amp;", "&").replace("&lt;", "<").replace("&gt;", ">").replace("&nbsp;", " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Start, end and text of every cue
+    fn cues(text: &str, extension: &str) -> Vec<(f64, Option<f64>, String)> {
+        parse(text.as_bytes(), Some(extension))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|cue| {
+                let text = cue.items
+                    .iter()
+                    .map(|item| {
+                        match item {
+                            SubtitleItem::Text(text) => text.clone(),
+                            SubtitleItem::Bitmap(_) => String::new(),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                (cue.start, cue.end, text)
+            })
+            .collect()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn timestamps() {
+        assert!(close(parse_timestamp("01:02:03,456").unwrap(), 3723.456));
+        assert!(close(parse_timestamp("01:02:03.456").unwrap(), 3723.456));
+        assert!(close(parse_timestamp("02:03.456").unwrap(), 123.456));
+        assert!(close(parse_timestamp("1:02:03.45").unwrap(), 3723.45));
+        assert_eq!(parse_timestamp("01:xx:03,456"), None);
+        assert_eq!(parse_timestamp(""), None);
+    }
+
+    #[test]
+    fn srt_with_multi_line_cues() {
+        let srt = "1\n00:00:01,000 --> 00:00:02,500\nFirst line\n<i>second</i> line\n\n\
+                   2\n00:00:03,000 --> 00:00:04,000\nNext\n";
+        let cues = cues(srt, "srt");
+        assert_eq!(cues.len(), 2);
+        assert!(close(cues[0].0, 1.0));
+        assert!(close(cues[0].1.unwrap(), 2.5));
+        assert_eq!(cues[0].2, "First line\nsecond line");
+        assert_eq!(cues[1].2, "Next");
+    }
+
+    #[test]
+    fn bom_and_crlf() {
+        let srt = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,000\r\nOne\r\nTwo\r\n\r\n\
+                   2\r\n00:00:03,000 --> 00:00:04,000\r\nThree\r\n";
+        let cues = cues(srt, "srt");
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0].2, "One\nTwo");
+        assert_eq!(cues[1].2, "Three");
+    }
+
+    #[test]
+    fn webvtt_without_hours_and_with_settings() {
+        let vtt = "WEBVTT\n\nNOTE a comment\n\n\
+                   intro\n00:01.000 --> 00:02.000 align:start line:0\n<c.yellow>Hello</c>\n\n\
+                   01:00:00.000 --> 01:00:01.500\nLater\n";
+        let cues = cues(vtt, "vtt");
+        assert_eq!(cues.len(), 2);
+        assert!(close(cues[0].0, 1.0));
+        assert!(close(cues[0].1.unwrap(), 2.0));
+        assert_eq!(cues[0].2, "Hello");
+        assert!(close(cues[1].0, 3600.0));
+        assert!(close(cues[1].1.unwrap(), 3601.5));
+    }
+
+    #[test]
+    fn ass_dialogue_with_commas_in_the_text() {
+        let ass = "[Script Info]\nTitle: test\n\n[Events]\n\
+                   Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+                   Dialogue: 0,0:00:05.00,0:00:06.50,Default,,0,0,0,,{\\i1}Well, well,\\Nwell{\\i0}\n\
+                   Comment: 0,0:00:07.00,0:00:08.00,Default,,0,0,0,,Not shown\n";
+        let cues = cues(ass, "ass");
+        assert_eq!(cues.len(), 1);
+        assert!(close(cues[0].0, 5.0));
+        assert!(close(cues[0].1.unwrap(), 6.5));
+        assert_eq!(cues[0].2, "Well, well,\nwell");
+    }
+
+    #[test]
+    fn cues_come_out_in_order() {
+        let srt = "2\n00:00:05,000 --> 00:00:06,000\nLater\n\n\
+                   1\n00:00:01,000 --> 00:00:02,000\nEarlier\n";
+        let cues = cues(srt, "srt");
+        assert_eq!(cues[0].2, "Earlier");
+        assert_eq!(cues[1].2, "Later");
+    }
+
+    #[test]
+    fn nothing_to_read() {
+        assert!(matches!(parse(b"WEBVTT\n", Some("vtt")), Err(SubtitleFileError::NoCues)));
+        assert!(
+            matches!(parse(b"", Some("sub")), Err(SubtitleFileError::UnsupportedFormat))
+        );
+    }
+}
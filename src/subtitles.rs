@@ -0,0 +1,280 @@
+use std::collections::VecDeque;
+
+use ffmpeg_next::{ self as ffmpeg, codec::Parameters, subtitle::Rect };
+use sdl3::{
+    pixels::PixelFormat,
+    render::{ BlendMode, Canvas, FRect, Texture },
+    video::Window,
+};
+
+use crate::{ osd::{ Anchor, draw_lines }, utils::destroy_texture };
+
+/// A decoded subtitle event, times are in seconds on the file's timeline.
+#[derive(Clone)]
+pub struct SubtitleCue {
+    pub start: f64,
+    // `None` when the cue stays up until the next one (PGS, DVB)
+    pub end: Option<f64>,
+    // Empty for the "clear the screen" events of bitmap formats
+    pub items: Vec<SubtitleItem>,
+}
+
+#[derive(Clone)]
+pub enum SubtitleItem {
+    Text(String),
+    Bitmap(SubtitleBitmap),
+}
+
+#[derive(Clone)]
+pub struct SubtitleBitmap {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    // ARGB8888, native endian like FFmpeg's palette
+    pub pixels: Vec<u8>,
+    // Size of the picture the position is relative to, usually the video's
+    pub reference: (u32, u32),
+}
+
+impl SubtitleCue {
+    /// Converts a decoded subtitle. `pts` is the packet's, in seconds, for decoders that do not
+    /// set one.
+    pub fn from_subtitle(
+        subtitle: &ffmpeg::Subtitle,
+        pts: Option<f64>,
+        reference: (u32, u32)
+    ) -> Option<SubtitleCue> {
+        let base = subtitle
+            .pts()
+            .map(|pts| (pts as f64) / f64::from(ffmpeg::ffi::AV_TIME_BASE))
+            .or(pts)?;
+        let start = base + (subtitle.start() as f64) / 1000.0;
+        let end = if subtitle.end() > subtitle.start() && subtitle.end() != u32::MAX {
+            Some(base + (subtitle.end() as f64) / 1000.0)
+        } else {
+            None
+        };
+        let items = subtitle
+            .rects()
+            .filter_map(|rect| {
+                match rect {
+                    Rect::Text(text) => Some(SubtitleItem::Text(text.get().trim().to_string())),
+                    Rect::Ass(ass) => Some(SubtitleItem::Text(plain_text(ass.get()))),
+                    Rect::Bitmap(bitmap) => {
+                        let raw = unsafe { &*bitmap.as_ptr() };
+                        bitmap_pixels(raw).map(|pixels| {
+                            SubtitleItem::Bitmap(SubtitleBitmap {
+                                x: raw.x.max(0) as u32,
+                                y: raw.y.max(0) as u32,
+                                width: raw.w as u32,
+                                height: raw.h as u32,
+                                pixels,
+                                reference,
+                            })
+                        })
+                    }
+                    Rect::None(_) => None,
+                }
+            })
+            .collect();
+        Some(SubtitleCue { start, end, items })
+    }
+}
+
+// Expands a palettized subtitle rect (PGS, DVB, VobSub) to ARGB8888
+fn bitmap_pixels(raw: &ffmpeg::ffi::AVSubtitleRect) -> Option<Vec<u8>> {
+    if raw.data[0].is_null() || raw.data[1].is_null() || raw.w <= 0 || raw.h <= 0 {
+        return None;
+    }
+    // Rows shorter than the picture, or stored bottom up, are not something to read from
+    if raw.linesize[0] < raw.w {
+        return None;
+    }
+    let (width, height) = (raw.w as usize, raw.h as usize);
+    let stride = raw.linesize[0] as usize;
+    let colors = raw.nb_colors.max(0) as usize;
+    let palette = unsafe { std::slice::from_raw_parts(raw.data[1] as *const u32, colors) };
+    // The last row does not have to be padded out to the stride
+    let indices = unsafe {
+        std::slice::from_raw_parts(raw.data[0], stride * (height - 1) + width)
+    };
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for &index in &indices[y * stride..y * stride + width] {
+            let color = palette.get(index as usize).copied().unwrap_or(0);
+            pixels.extend_from_slice(&color.to_ne_bytes());
+        }
+    }
+    Some(pixels)
+}
+
+/// Text of an ASS event as muxed in Matroska ("ReadOrder,Layer,Style,Name,MarginL,MarginR,
+/// MarginV,Effect,Text"), without the override tags.
+pub fn plain_text(event: &str) -> String {
+    let text = event.strip_prefix("Dialogue:").unwrap_or(event);
+    strip_ass_tags(text.splitn(9, ',').last().unwrap_or(text))
+}
+
+/// Removes the `{...}` override blocks from ASS text and turns its escapes into plain text.
+pub fn strip_ass_tags(text: &str) -> String {
+    let mut plain = String::new();
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '{' => {
+                in_tag = true;
+            }
+            '}' => {
+                in_tag = false;
+            }
+            _ if !in_tag => plain.push(c),
+            _ => {}
+        }
+    }
+    plain.replace("\\N", "\n").replace("\\n", "\n").replace("\\h", " ").trim().to_string()
+}
+
+/// Width and height from codec parameters, 0 when the stream does not carry them.
+pub fn parameters_size(parameters: &Parameters) -> (u32, u32) {
+    let raw = unsafe { &*parameters.as_ptr() };
+    (raw.width.max(0) as u32, raw.height.max(0) as u32)
+}
+
+/// The cues on screen and the textures of their bitmaps.
+pub struct SubtitleOverlay {
+    visible: bool,
+    active: Vec<SubtitleCue>,
+    textures: Vec<(FRect, Texture)>,
+    // Picture rectangle the textures were laid out for
+    laid_out_for: Option<FRect>,
+    // What is on screen changed since the last draw
+    dirty: bool,
+}
+
+impl SubtitleOverlay {
+    pub fn new() -> SubtitleOverlay {
+        SubtitleOverlay {
+            visible: true,
+            active: Vec::new(),
+            textures: Vec::new(),
+            laid_out_for: None,
+            dirty: false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.dirty |= !self.active.is_empty();
+        self.active.clear();
+        self.destroy_textures();
+        self.laid_out_for = None;
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.dirty |= self.visible != visible;
+        self.visible = visible;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Takes the cues that are due at `position` (seconds) off `queue` and drops the ones that
+    /// ended. Returns whether the overlay has to be drawn again.
+    pub fn update(&mut self, position: f64, queue: &mut VecDeque<SubtitleCue>) -> bool {
+        let mut changed = false;
+        while let Some(cue) = queue.front() && cue.start <= position {
+            let cue = queue.pop_front().unwrap();
+            // A cue without an end stays until the next one shows up
+            self.active.retain(|active| active.end.is_some());
+            self.active.push(cue);
+            changed = true;
+        }
+        let before = self.active.len();
+        self.active.retain(|cue| cue.end.map_or(true, |end| end > position));
+        // Clear events have nothing to draw once they took the previous cue down
+        self.active.retain(|cue| !cue.items.is_empty());
+        if changed || self.active.len() != before {
+            self.destroy_textures();
+            self.laid_out_for = None;
+            self.dirty = true;
+        }
+        self.dirty
+    }
+
+    fn destroy_textures(&mut self) {
+        for (_, texture) in self.textures.drain(..) {
+            destroy_texture(texture);
+        }
+    }
+
+    /// Draws the active cues over whatever is on the canvas. Bitmaps are placed on `picture`,
+    /// where the video is drawn, or on the whole canvas without one.
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, picture: Option<FRect>) {
+        self.dirty = false;
+        if !self.visible || self.active.is_empty() {
+            return;
+        }
+        let Ok(size) = canvas.output_size() else {
+            return;
+        };
+        let picture = picture.unwrap_or(FRect::new(0.0, 0.0, size.0 as f32, size.1 as f32));
+        if self.laid_out_for != Some(picture) {
+            self.destroy_textures();
+            self.textures = self.active
+                .iter()
+                .flat_map(|cue| cue.items.iter())
+                .filter_map(|item| {
+                    match item {
+                        SubtitleItem::Bitmap(bitmap) => bitmap_texture(canvas, bitmap, picture),
+                        SubtitleItem::Text(_) => None,
+                    }
+                })
+                .collect();
+            self.laid_out_for = Some(picture);
+        }
+        for (rect, texture) in &self.textures {
+            let _ = canvas.copy(texture, None, *rect);
+        }
+
+        let lines: Vec<&str> = self.active
+            .iter()
+            .flat_map(|cue| cue.items.iter())
+            .filter_map(|item| {
+                match item {
+                    SubtitleItem::Text(text) => Some(text.as_str()),
+                    SubtitleItem::Bitmap(_) => None,
+                }
+            })
+            .flat_map(|text| text.lines())
+            .collect();
+        draw_lines(canvas, &lines, size, Anchor::Bottom);
+    }
+}
+
+fn bitmap_texture(
+    canvas: &Canvas<Window>,
+    bitmap: &SubtitleBitmap,
+    picture: FRect
+) -> Option<(FRect, Texture)> {
+    let mut texture = canvas
+        .texture_creator()
+        .create_texture_static(PixelFormat::ARGB8888, bitmap.width, bitmap.height)
+        .ok()?;
+    texture.update(None, &bitmap.pixels, (bitmap.width * 4) as usize).ok()?;
+    texture.set_blend_mode(BlendMode::Blend);
+    let reference = if bitmap.reference.0 == 0 || bitmap.reference.1 == 0 {
+        (picture.w as u32, picture.h as u32)
+    } else {
+        bitmap.reference
+    };
+    let scale_x = picture.w / (reference.0.max(1) as f32);
+    let scale_y = picture.h / (reference.1.max(1) as f32);
+    let rect = FRect::new(
+        picture.x + (bitmap.x as f32) * scale_x,
+        picture.y + (bitmap.y as f32) * scale_y,
+        (bitmap.width as f32) * scale_x,
+        (bitmap.height as f32) * scale_y
+    );
+    Some((rect, texture))
+}
@@ -2,15 +2,24 @@ use std::sync::LazyLock;
 
 use ffmpeg_next::{
     self as ffmpeg, Rational, Stream,
-    codec::Context,
+    codec::{Context, threading},
     decoder::Audio,
-    format::{Pixel, context::Input},
+    format::{Pixel, context::Input, stream::Disposition},
     media,
     software::{self, scaling::Flags},
 };
-use sdl3::audio::{AudioFormat, AudioSpec};
+use sdl3::{
+    audio::{AudioFormat, AudioSpec},
+    render::Texture,
+};
 
-use crate::{constants::ConvFormat, mplayer::OPTS};
+use crate::{
+    audio::ReplayGain,
+    color::Tonemap,
+    constants::ConvFormat,
+    filters::{Deinterlace, DeinterlaceMode},
+    mplayer::OPTS,
+};
 
 #[derive(Clone)]
 pub struct Range {
@@ -53,7 +62,10 @@ impl Range {
 }
 
 // Copied from https://github.com/zmwangx/rust-ffmpeg/blob/master/examples/metadata.rs
-pub fn print_context_data(context: &Input) -> Result<(), ffmpeg::Error> {
+pub fn print_context_data(
+    context: &Input,
+    threading: threading::Config
+) -> Result<(), ffmpeg::Error> {
     for (k, v) in context.metadata().iter() {
         println!("{}: {}", k, v);
     }
@@ -87,13 +99,21 @@ pub fn print_context_data(context: &Input) -> Result<(), ffmpeg::Error> {
         println!("\tdisposition: {:?}", stream.disposition());
         println!("\tdiscard: {:?}", stream.discard());
         println!("\trate: {}", stream.rate());
+        if let Some(language) = stream.metadata().get("language") {
+            println!("\tlanguage: {}", language);
+        }
 
-        let codec = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
+        let mut codec = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
         println!("\tmedium: {:?}", codec.medium());
         println!("\tid: {:?}", codec.id());
+        // Opened the way the decode threads open theirs, the threading shown is what FFmpeg went
+        // with: fewer threads (or none) when the codec does not support the requested kind
+        codec.set_threading(threading);
 
         if codec.medium() == ffmpeg::media::Type::Video {
             if let Ok(video) = codec.decoder().video() {
+                let threading = video.threading();
+                println!("\tthreading: {:?}, {} threads", threading.kind, threading.count);
                 println!("\tbit_rate: {}", video.bit_rate());
                 println!("\tmax_rate: {}", video.max_bit_rate());
                 println!("\tdelay: {}", video.delay());
@@ -115,6 +135,8 @@ pub fn print_context_data(context: &Input) -> Result<(), ffmpeg::Error> {
             }
         } else if codec.medium() == ffmpeg::media::Type::Audio {
             if let Ok(audio) = codec.decoder().audio() {
+                let threading = audio.threading();
+                println!("\tthreading: {:?}, {} threads", threading.kind, threading.count);
                 println!("\tbit_rate: {}", audio.bit_rate());
                 println!("\tmax_rate: {}", audio.max_bit_rate());
                 println!("\tdelay: {}", audio.delay());
@@ -167,6 +189,81 @@ pub struct MDecodeOptions {
     pub look_range: Range,
     pub window_default_size: (u32, u32),
     pub pixel_format: Pixel,
+    // Decoder threads, 0 lets FFmpeg pick one per core
+    pub thread_count: usize,
+    // Frame threading scales best but holds back `thread_count` frames, slice threading has no
+    // delay but only helps codecs that split frames into slices
+    pub thread_type: threading::Type,
+    // Which video and audio stream to play when a file is opened
+    pub video_stream: StreamSelection,
+    pub audio_stream: StreamSelection,
+    // `None` leaves subtitles off
+    pub subtitle_stream: Option<StreamSelection>,
+    // Decoded formats the renderer can upload as they are, frames in these skip swscale
+    pub native_formats: Vec<Pixel>,
+    // Spec every decoded audio frame is converted to, `None` leaves the frames as decoded
+    pub audio_output: Option<AudioSpec>,
+    // libavfilter chain the decoded frames go through before scaling, e.g. "crop=iw/2:ih:0:0"
+    pub video_filters: Option<String>,
+    pub deinterlace: Deinterlace,
+    // Clockwise degrees in place of the orientation from the stream's metadata, `None` follows it
+    pub rotation: Option<u32>,
+    // What PQ/HLG video goes through on its way to the SDR output
+    pub tonemap: Tonemap,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamSelection {
+    // What FFmpeg considers the best stream of the kind
+    Best,
+    // Stream index in the container, as listed by `print_context_data`
+    Index(usize),
+    // ISO 639 language tag, e.g. "eng", matched against the stream metadata
+    Language(String),
+}
+
+impl StreamSelection {
+    /// Finds the stream of `kind` matching the selection, falling back to the best one when there
+    /// is no match.
+    pub fn find<'a>(&self, input: &'a Input, kind: media::Type) -> Option<Stream<'a>> {
+        let selected = match self {
+            StreamSelection::Best => None,
+            StreamSelection::Index(index) =>
+                input.stream(*index).filter(|stream| stream.parameters().medium() == kind),
+            StreamSelection::Language(language) =>
+                input
+                    .streams()
+                    .filter(|stream| stream.parameters().medium() == kind)
+                    .find(|stream| {
+                        stream
+                            .metadata()
+                            .get("language")
+                            .is_some_and(|tag| tag.eq_ignore_ascii_case(language))
+                    }),
+        };
+        if selected.is_none() && *self != StreamSelection::Best {
+            println!("no {:?} stream matches {:?}, using the best one", kind, self);
+        }
+        selected.or_else(|| input.streams().best(kind))
+    }
+}
+
+impl std::str::FromStr for StreamSelection {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err(()),
+            "auto" => Ok(StreamSelection::Best),
+            _ =>
+                Ok(
+                    s
+                        .parse()
+                        .map(StreamSelection::Index)
+                        .unwrap_or(StreamSelection::Language(s.to_string()))
+                ),
+        }
+    }
 }
 impl Default for MDecodeOptions {
     fn default() -> Self {
@@ -175,6 +272,30 @@ impl Default for MDecodeOptions {
             scaling_flag: Flags::BILINEAR,
             window_default_size: (1280, 720),
             pixel_format: Pixel::RGBA,
+            thread_count: 0,
+            thread_type: threading::Type::Frame,
+            video_stream: StreamSelection::Best,
+            audio_stream: StreamSelection::Best,
+            subtitle_stream: Some(StreamSelection::Best),
+            native_formats: Vec::new(),
+            audio_output: None,
+            video_filters: None,
+            deinterlace: Deinterlace {
+                mode: DeinterlaceMode::Auto,
+                double_rate: false,
+            },
+            rotation: None,
+            tonemap: Tonemap::Off,
+        }
+    }
+}
+
+impl MDecodeOptions {
+    pub fn threading(&self) -> threading::Config {
+        threading::Config {
+            kind: self.thread_type,
+            count: self.thread_count,
+            ..Default::default()
         }
     }
 }
@@ -184,54 +305,80 @@ impl Default for &MDecodeOptions {
         &OPTS
     }
 }
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct MediaInfo {
+    // None for audio-only files, cover art does not count as video
+    pub video: Option<VideoInfo>,
+    pub audio_spec: Option<AudioSpec>,
+    // "artist - title" from the container tags, when there are any
+    pub title: Option<String>,
+    pub replay_gain: ReplayGain,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VideoInfo {
     pub v_width: u32,
     pub v_height: u32,
     pub video_rate: Rational,
     pub aspect_ratio: Rational,
     pub frame_time_ns: i32,
     pub frame_time_ms: i32,
-    pub audio_spec: AudioSpec,
     pub time_base: Rational,
 }
 
 impl MediaInfo {
-    pub fn get_media_info_from_input(input: Input) -> MediaInfo {
-        let v_stream = input
+    pub fn get_media_info_from_input(input: &Input) -> MediaInfo {
+        let video = input
             .streams()
-            .best(media::Type::Video)
-            .expect("No video stream found");
-        let a_stream = input
+            .filter(|stream| stream.parameters().medium() == media::Type::Video)
+            .find(|stream| !stream.disposition().contains(Disposition::ATTACHED_PIC))
+            .and_then(|v_stream| {
+                let video_decoder = Context::from_parameters(v_stream.parameters())
+                    .ok()?
+                    .decoder()
+                    .video()
+                    .ok()?;
+                let rate = v_stream.rate();
+                Some(VideoInfo {
+                    v_width: video_decoder.width(),
+                    v_height: video_decoder.height(),
+                    video_rate: rate,
+                    aspect_ratio: video_decoder.aspect_ratio(),
+                    frame_time_ms: if rate.0 != 0 { frame_time_ms(rate) } else { 0 },
+                    frame_time_ns: if rate.0 != 0 { frame_time_ns(rate) } else { 0 },
+                    time_base: video_decoder.time_base(),
+                })
+            });
+
+        let audio_spec = input
             .streams()
             .best(media::Type::Audio)
-            .expect("No audio stream found");
+            .and_then(|a_stream| Context::from_parameters(a_stream.parameters()).ok())
+            .and_then(|context| context.decoder().audio().ok())
+            .map(|audio_decoder| AudioSpec {
+                freq: Some(audio_decoder.rate() as i32),
+                channels: Some(audio_decoder.channels().into()),
+                format: Some(audio_decoder.format().convert()),
+            });
 
-        let video_decoder = Context::from_parameters(v_stream.parameters())
-            .expect("Failed to get video decoder context")
-            .decoder()
-            .video()
-            .expect("Failed to get video decoder");
+        let metadata = input.metadata();
+        let title = match (metadata.get("artist"), metadata.get("title")) {
+            (Some(artist), Some(title)) => Some(format!("{} - {}", artist, title)),
+            (None, Some(title)) => Some(title.to_string()),
+            _ => None,
+        };
 
-        let audio_decoder = Context::from_parameters(a_stream.parameters())
-            .expect("Failed to get audio decoder context")
-            .decoder()
-            .audio()
-            .expect("Failed to get audio decoder");
+        // Ogg and Opus keep the tags on the stream rather than the container
+        let mut replay_gain = ReplayGain::from_metadata(&metadata);
+        if replay_gain.is_empty() && let Some(stream) = input.streams().best(media::Type::Audio) {
+            replay_gain = ReplayGain::from_metadata(&stream.metadata());
+        }
 
         MediaInfo {
-            v_width: video_decoder.width(),
-            v_height: video_decoder.height(),
-            video_rate: v_stream.rate(),
-            aspect_ratio: video_decoder.aspect_ratio(),
-            frame_time_ms: frame_time_ms(v_stream.rate()),
-            frame_time_ns: frame_time_ns(v_stream.rate()),
-            audio_spec: AudioSpec {
-                freq: Some(audio_decoder.rate() as i32),
-                channels: Some(audio_decoder.channels().into()),
-                format: Some(audio_decoder.format().convert()),
-            },
-            time_base: video_decoder.time_base(),
+            video,
+            audio_spec,
+            title,
+            replay_gain,
         }
     }
 }
@@ -285,6 +432,16 @@ pub fn convert_pts(pts: i64, from_base: Rational, to_base: Rational) -> u32 {
     ((pts as f64 * res.0 as f64) / res.1 as f64) as u32
 }
 
+// "m:ss", or "h:mm:ss" past the hour
+pub fn format_duration_ms(ms: u32) -> String {
+    let secs = ms / 1000;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
 pub fn move_terminal_cursor(x: u32, y: u32) {
     print!("{esc}[{};{}H", y, x, esc = 27 as char,);
 }
@@ -296,3 +453,12 @@ pub fn print_at_line(text: String, x: u32, y: u32) {
 pub fn clear_screen() {
     print!("{}[2J", 27 as char);
 }
+
+/// Frees `texture`. The sdl3 crate is built with `unsafe_textures`, so dropping a texture leaks
+/// it: every one that gets replaced or cleared has to go through here, while the renderer that
+/// made it is still around.
+pub fn destroy_texture(texture: Texture) {
+    unsafe {
+        texture.destroy();
+    }
+}
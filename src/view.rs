@@ -0,0 +1,83 @@
+use ffmpeg_next::Rational;
+use sdl3::render::FRect;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViewMode {
+    // The whole picture, with bars where its shape differs from the window's
+    Fit,
+    // Covers the window, cropping what does not fit
+    Fill,
+    // Covers the window, ignoring the aspect ratio
+    Stretch,
+}
+
+/// Where the video goes in the window.
+#[derive(Debug, Clone, Copy)]
+pub struct VideoView {
+    pub mode: ViewMode,
+    // On top of the mode, 1.0 leaves it as is
+    pub zoom: f32,
+    // Moves the picture's center, in fractions of the window size
+    pub pan: (f32, f32),
+    // Width over height of the picture as displayed, `None` takes the shape of the frames
+    pub display_aspect: Option<f32>,
+}
+
+impl VideoView {
+    pub fn new() -> VideoView {
+        VideoView {
+            mode: ViewMode::Fit,
+            zoom: 1.0,
+            pan: (0.0, 0.0),
+            display_aspect: None,
+        }
+    }
+
+    /// The shape of a `width` x `height` picture once its sample aspect ratio is applied.
+    /// FFmpeg reports an unknown SAR as 0/1, those pixels are taken as square.
+    pub fn display_aspect(width: u32, height: u32, sample_aspect: Rational) -> Option<f32> {
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let sample_aspect = if sample_aspect.0 > 0 && sample_aspect.1 > 0 {
+            (sample_aspect.0 as f32) / (sample_aspect.1 as f32)
+        } else {
+            1.0
+        };
+        Some(((width as f32) * sample_aspect) / (height as f32))
+    }
+
+    /// The rectangle a `picture` sized frame is drawn to on an `output` sized canvas. It can go
+    /// past the canvas edges when filling, zooming or panning.
+    pub fn rect(&self, output: (u32, u32), picture: (u32, u32)) -> FRect {
+        let (out_width, out_height) = (output.0 as f32, output.1.max(1) as f32);
+        let aspect = self.display_aspect.unwrap_or((picture.0 as f32) / (picture.1.max(1) as f32));
+        let wider = out_width / out_height > aspect;
+        let (width, height) = match self.mode {
+            ViewMode::Stretch => (out_width, out_height),
+            ViewMode::Fit if wider => (out_height * aspect, out_height),
+            ViewMode::Fit => (out_width, out_width / aspect),
+            ViewMode::Fill if wider => (out_width, out_width / aspect),
+            ViewMode::Fill => (out_height * aspect, out_height),
+        };
+        let (width, height) = (width * self.zoom, height * self.zoom);
+        FRect::new(
+            (out_width - width) / 2.0 + self.pan.0 * out_width,
+            (out_height - height) / 2.0 + self.pan.1 * out_height,
+            width,
+            height
+        )
+    }
+
+    /// What the decoder should scale to: the picture's size on screen, held to the canvas size
+    /// when it is bigger so zooming in does not make swscale produce huge frames. The renderer
+    /// stretches the rest of the way.
+    pub fn scale_size(&self, output: (u32, u32), picture: (u32, u32)) -> (u32, u32) {
+        let rect = self.rect(output, picture);
+        if rect.w < 1.0 || rect.h < 1.0 {
+            return output;
+        }
+        let shrink = ((output.0 as f32) / rect.w).min((output.1 as f32) / rect.h).min(1.0);
+        ((rect.w * shrink).round() as u32, (rect.h * shrink).round() as u32)
+    }
+}